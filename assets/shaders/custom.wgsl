@@ -3,23 +3,131 @@
 
 // NOTE: Bindings must come before functions that use them!
 #import bevy_pbr::mesh_functions
+#import bevy_pbr::shadows
+
+// Time, camera position and viewport size come from the view bindings (globals and view)
+struct ShaderGlobals {
+    rolling_log_bending: f32,
+    spherical_bending: f32,
+    player_position: vec3<f32>,
+    fog_min: f32,
+    fog_max: f32,
+    fog_color: vec3<f32>,
+    ambient: vec3<f32>,
+    vignette_radius: f32,
+    vignette_softness: f32,
+    light_direction: vec3<f32>,
+    sun_color: vec3<f32>,
+    fill_light: f32,
+    glow_intensity: f32,
+    wind: vec2<f32>,
+    wind_frequency: f32,
+    wind_push_radius: f32,
+    wind_push_strength: f32,
+    glow_pulse_speed: f32,
+    glow_lights: array<vec4<f32>, 8u>,
+    glow_light_colors: array<vec4<f32>, 8u>,
+    glow_light_count: u32,
+    occlusion_fade: f32,
+    occlusion_radius: f32,
+};
+
+// Bound after the view, material and mesh groups
+@group(3) @binding(0)
+var<uniform> shader_globals: ShaderGlobals;
+
+#ifdef BLOCK_ATLAS
+struct BlockDamageEntry {
+    position: vec3<i32>,
+    // 0 marks an empty entry
+    damage: f32,
+    last_hit: f32,
+};
+
+// Hash table of the damaged blocks, see BlockDamageTable in shaders.rs
+struct BlockDamageTable {
+    mask: u32,
+    entries: array<BlockDamageEntry>,
+};
+
+@group(3) @binding(1)
+var<storage> block_damage: BlockDamageTable;
+
+// Blocks share one material, the layer index picks the texture and colour
+struct BlockMaterial {
+    layer_colors: array<vec4<f32>, 16u>,
+    // Pulse amount in alpha
+    layer_emissive: array<vec4<f32>, 16u>,
+};
 
 @group(1) @binding(0)
-var<uniform> time: f32;
+var<uniform> material: BlockMaterial;
+@group(1) @binding(1)
+var color_texture: texture_2d_array<f32>;
+#else
+struct CustomMaterial {
+    color: vec3<f32>,
+    alpha_cutoff: f32,
+    alpha_blend: u32,
+    uv_rect: vec4<f32>,
+    bend_from_origin: u32,
+    sway: f32,
+    emissive: vec3<f32>,
+    emissive_pulse: f32,
+    fade_occluding: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> material: CustomMaterial;
 @group(1) @binding(1)
-var<uniform> bending: f32;
-@group(1) @binding(2)
-var<uniform> cam_position: vec3<f32>;
-@group(1) @binding(3)
-var<uniform> color: vec3<f32>;
-@group(1) @binding(4)
 var color_texture: texture_2d<f32>;
-@group(1) @binding(5)
+#endif
+
+@group(1) @binding(2)
 var color_sampler: sampler;
-@group(1) @binding(6)
-var<uniform> player_position: vec3<f32>;
-@group(1) @binding(7)
-var<uniform> viewport_size: vec2<f32>;
+
+// Billboards are bent as a whole from their origin so they do not skew
+fn bend_reference(world_position: vec3<f32>) -> vec3<f32> {
+#ifndef BLOCK_ATLAS
+    if (material.bend_from_origin != 0u) {
+        return mesh.model[3].xyz;
+    }
+#endif
+    return world_position;
+}
+
+#ifndef BLOCK_ATLAS
+// Horizontal wind displacement of a plane_mesh sprite. The bottom edge stays in place.
+fn sway_offset(local_position: vec3<f32>) -> vec2<f32> {
+    // plane_mesh sprites are stood up so local +z points up
+    let weight = clamp(local_position.z + 0.5, 0.0, 1.0) * material.sway;
+    let origin = mesh.model[3].xyz;
+
+    // Each sprite gets its own phase so they do not move in lockstep
+    let phase = dot(origin.xz, vec2<f32>(0.37, 0.61));
+    let gust = 0.6 + 0.4 * sin(globals.time * shader_globals.wind_frequency + phase);
+    var offset = shader_globals.wind * gust;
+
+    // Bend away from the player when walking through
+    let away = origin.xz - shader_globals.player_position.xz;
+    let dist = length(away);
+    if (dist > 0.001) {
+        let push = 1.0 - smoothstep(0.0, shader_globals.wind_push_radius, dist);
+        offset += away / dist * push * shader_globals.wind_push_strength;
+    }
+    return offset * weight;
+}
+#endif
+
+// Same as WorldCurvature::bend_offset on the CPU
+fn curvature_offset(world_position: vec3<f32>) -> f32 {
+    let d = world_position - view.world_position.xyz;
+    return -(shader_globals.rolling_log_bending * d.z * d.z + shader_globals.spherical_bending * dot(d.xz, d.xz));
+}
+
+fn bend_offset(world_position: vec3<f32>) -> f32 {
+    return curvature_offset(bend_reference(world_position));
+}
 
 struct Vertex {
     @location(0) position: vec3<f32>,
@@ -37,11 +145,17 @@ struct Vertex {
     @location(5) joint_indices: vec4<u32>,
     @location(6) joint_weights: vec4<f32>,
 #endif
+#ifdef BLOCK_ATLAS
+    @location(7) layer: u32,
+#endif
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     #import bevy_pbr::mesh_vertex_output
+#ifdef BLOCK_ATLAS
+    @location(5) @interpolate(flat) layer: u32,
+#endif
 };
 
 @vertex
@@ -64,10 +178,19 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 #ifdef VERTEX_COLORS
     out.color = vertex.color;
 #endif
+#ifdef BLOCK_ATLAS
+    out.layer = vertex.layer;
+#endif
 
     out.world_position = mesh_position_local_to_world(model, vec4<f32>(vertex.position.x, vertex.position.y, vertex.position.z, 1.0));
-    var dist_from_camera = (out.world_position.xyz - cam_position).z;
-    out.world_position.y += pow(dist_from_camera, 2.0) * -bending;
+#ifndef BLOCK_ATLAS
+    if (material.sway > 0.0) {
+        let sway = sway_offset(vertex.position);
+        out.world_position.x += sway.x;
+        out.world_position.z += sway.y;
+    }
+#endif
+    out.world_position.y += bend_offset(out.world_position.xyz);
     out.clip_position = mesh_position_world_to_clip(out.world_position);
 
     return out;
@@ -76,11 +199,14 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 struct FragmentInput {
     @builtin(position) frag_pos: vec4<f32>,
     #import bevy_pbr::mesh_vertex_output
+#ifdef BLOCK_ATLAS
+    @location(5) @interpolate(flat) layer: u32,
+#endif
 };
 
 fn fog_factor(d: f32) -> f32 {
-    var fog_max = 9.0;
-    var fog_min = 0.0;
+    var fog_max = shader_globals.fog_max;
+    var fog_min = shader_globals.fog_min;
     if (d>=fog_max) {
         return 1.0;
     }
@@ -90,11 +216,164 @@ fn fog_factor(d: f32) -> f32 {
     return 1.0 - (fog_max - d) / (fog_max - fog_min);
 }
 
+// Shadow maps are rendered without the bending, so lookups need the original position
+fn unbend(bent: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(bent.x, bent.y - bend_offset(bent), bent.z);
+}
+
+#ifdef BLOCK_ATLAS
+let DAMAGE_STAGES: f32 = 4.0;
+let HIT_FLASH_DURATION: f32 = 0.15;
+// Same as NEVER_HIT in constants.rs
+let NEVER_HIT: f32 = -1000.0;
+
+// Same as block_damage_slot in shaders.rs, block_damage_slots_are_pinned there lists
+// slots to check changes against
+fn block_damage_slot(block: vec3<i32>) -> u32 {
+    let hash = (u32(block.x) * 73856093u) ^ (u32(block.y) * 19349663u) ^ (u32(block.z) * 83492791u);
+    return hash & block_damage.mask;
+}
+
+// Damage ratio and time of the last hit of the block, the table is never full so an empty
+// entry ends the search
+fn find_block_damage(block: vec3<i32>) -> vec2<f32> {
+    var slot = block_damage_slot(block);
+    loop {
+        let entry = block_damage.entries[slot];
+        if (entry.damage <= 0.0) {
+            return vec2<f32>(0.0, NEVER_HIT);
+        }
+        if (all(entry.position == block)) {
+            return vec2<f32>(entry.damage, entry.last_hit);
+        }
+        slot = (slot + 1u) & block_damage.mask;
+    }
+    return vec2<f32>(0.0, NEVER_HIT);
+}
+
+fn hash2(p: vec2<f32>) -> vec2<f32> {
+    let q = vec2<f32>(dot(p, vec2<f32>(127.1, 311.7)), dot(p, vec2<f32>(269.5, 183.3)));
+    return fract(sin(q) * 43758.5453);
+}
+
+// Distance to the nearest edge of a voronoi cell, used as the crack pattern
+fn crack_distance(uv: vec2<f32>) -> f32 {
+    let cell = floor(uv);
+    let local = fract(uv);
+
+    var nearest_offset = vec2<f32>(0.0);
+    var nearest_dist = 8.0;
+    var y = -1;
+    loop {
+        if (y > 1) { break; }
+        var x = -1;
+        loop {
+            if (x > 1) { break; }
+            let offset = vec2<f32>(f32(x), f32(y));
+            let r = offset + hash2(cell + offset) - local;
+            let d = dot(r, r);
+            if (d < nearest_dist) {
+                nearest_dist = d;
+                nearest_offset = r;
+            }
+            x = x + 1;
+        }
+        y = y + 1;
+    }
+
+    var edge_dist = 8.0;
+    y = -1;
+    loop {
+        if (y > 1) { break; }
+        var x = -1;
+        loop {
+            if (x > 1) { break; }
+            let offset = vec2<f32>(f32(x), f32(y));
+            let r = offset + hash2(cell + offset) - local;
+            if (dot(r - nearest_offset, r - nearest_offset) > 0.0001) {
+                edge_dist = min(edge_dist, dot(0.5 * (nearest_offset + r), normalize(r - nearest_offset)));
+            }
+            x = x + 1;
+        }
+        y = y + 1;
+    }
+    return edge_dist;
+}
+
+// Darkens the cracks and whitens the block right after a hit.
+// damage.x is the ratio of lost health, damage.y the time of the last hit.
+fn apply_damage(color: vec3<f32>, uv: vec2<f32>, damage: vec2<f32>) -> vec3<f32> {
+    var result = color;
+    let stage = ceil(damage.x * DAMAGE_STAGES);
+    if (stage > 0.0) {
+        // Each stage adds finer cracks and widens the existing ones
+        let width = 0.02 * stage;
+        let crack = 1.0 - smoothstep(width, width + 0.02, crack_distance(fract(uv) * (1.0 + stage)));
+        result = mix(result, result * 0.15, crack);
+    }
+
+    let flash = clamp(1.0 - (globals.time - damage.y) / HIT_FLASH_DURATION, 0.0, 1.0);
+    return mix(result, vec3<f32>(1.0), flash * 0.6);
+}
+#endif
+
+// Same as glow_pulse in glow.rs
+fn glow_pulse(amount: f32, phase: f32) -> f32 {
+    return 1.0 - amount * (0.5 + 0.5 * sin(globals.time * shader_globals.glow_pulse_speed + phase));
+}
+
+// Light from the emissive blocks near the player
+fn glow_lighting(position: vec3<f32>, N: vec3<f32>) -> vec3<f32> {
+    var light = vec3<f32>(0.0);
+    for (var i = 0u; i < shader_globals.glow_light_count; i = i + 1u) {
+        let glow = shader_globals.glow_lights[i];
+        let to_light = glow.xyz - position;
+        let dist = length(to_light);
+        let falloff = clamp(1.0 - dist / glow.w, 0.0, 1.0);
+        let facing = max(dot(N, to_light / max(dist, 0.001)), 0.0);
+        light += shader_globals.glow_light_colors[i].rgb * falloff * falloff * facing;
+    }
+    return light;
+}
+
+// Ordered dither threshold between 0 and 1
+fn bayer4(frag_xy: vec2<f32>) -> f32 {
+    var matrix = array<f32, 16>(0.0, 8.0, 2.0, 10.0, 12.0, 4.0, 14.0, 6.0, 3.0, 11.0, 1.0, 9.0, 15.0, 7.0, 13.0, 5.0);
+    let x = u32(frag_xy.x) % 4u;
+    let y = u32(frag_xy.y) % 4u;
+    return (matrix[y * 4u + x] + 0.5) / 16.0;
+}
+
+// True for fragments in front of the player around its position on screen, while it is hidden
+fn occlusion_discard(frag_pos: vec4<f32>, world_position: vec3<f32>) -> bool {
+    if (shader_globals.occlusion_fade <= 0.0) {
+        return false;
+    }
+
+    // The player billboard is bent by its origin
+    var player = shader_globals.player_position;
+    player.y += curvature_offset(player);
+    if (distance(world_position, view.world_position.xyz) > distance(player, view.world_position.xyz) - 0.5) {
+        return false;
+    }
+
+    let player_clip = view.view_proj * vec4<f32>(player, 1.0);
+    if (player_clip.w <= 0.0) {
+        return false;
+    }
+    let player_ndc = player_clip.xy / player_clip.w;
+    let player_screen = vec2<f32>(player_ndc.x * 0.5 + 0.5, 0.5 - player_ndc.y * 0.5) * view.viewport.zw;
+    let screen_dist = distance(frag_pos.xy, player_screen) / view.viewport.w;
+    let radius = shader_globals.occlusion_radius;
+    let amount = shader_globals.occlusion_fade * (1.0 - smoothstep(radius * 0.6, radius, screen_dist));
+    return amount > bayer4(frag_pos.xy);
+}
+
 fn vignette(viewuv: vec2<f32>) -> f32 {
     var position = viewuv - vec2<f32>(0.5, 0.5);
     var dist = length(position);
-    var radius = 0.7;
-    var softness = 0.35;
+    var radius = shader_globals.vignette_radius;
+    var softness = shader_globals.vignette_softness;
     return smoothstep(radius, radius - softness, dist);
 }
 
@@ -103,29 +382,69 @@ fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
 #ifdef VERTEX_COLORS
     return in.color;
 #else
-    let viewport = in.frag_pos.xy / viewport_size;
+    let viewport = in.frag_pos.xy / view.viewport.zw;
 
-    var texCol = textureSample(color_texture, color_sampler, in.uv);
-    if (texCol.r == 0.0 && texCol.g == 0.0 && texCol.b == 0.0) {
+    // Chunk meshes stretch UVs over merged faces, so the texture is repeated here
+#ifdef BLOCK_ATLAS
+    var texCol = textureSample(color_texture, color_sampler, fract(in.uv), i32(in.layer));
+    var color = material.layer_colors[in.layer].rgb;
+    let layer_emissive = material.layer_emissive[in.layer];
+    // Blocks are always opaque
+    var alpha = 1.0;
+    if (occlusion_discard(in.frag_pos, in.world_position.xyz)) {
+        discard;
+    }
+#else
+    let uv = fract(in.uv) * material.uv_rect.zw + material.uv_rect.xy;
+    var texCol = textureSample(color_texture, color_sampler, uv);
+    var color = material.color;
+    var alpha = texCol.a;
+    if (alpha < material.alpha_cutoff) {
         discard;
     }
+    if (material.alpha_blend == 0u) {
+        alpha = 1.0;
+    }
+    if (material.fade_occluding != 0u && occlusion_discard(in.frag_pos, in.world_position.xyz)) {
+        discard;
+    }
+#endif
 
-    var ambient = vec3<f32>(0.01, 0.01, 0.01);
-    var lightDir = vec3<f32>(0.5, -0.7, 0.2);
+    var ambient = shader_globals.ambient;
 
-    var fog = fog_factor(distance(in.world_position.xyz, player_position));
+    var fog = fog_factor(distance(in.world_position.xyz, shader_globals.player_position));
 
     var N = normalize(in.world_normal);
     var V = normalize(view.world_position.xyz - in.world_position.xyz);
-    var diff = max(dot(N, V), 0.0001) * color;
-    diff.r *= texCol.r;
-    diff.g *= texCol.g;
-    diff.b *= texCol.b;
+    var L = -shader_globals.light_direction;
+
+    var shadow = 1.0;
+    if (lights.n_directional_lights > 0u
+            && (lights.directional_lights[0].flags & DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+        shadow = fetch_directional_shadow(0u, vec4<f32>(unbend(in.world_position.xyz), 1.0), N);
+    }
+
+    // The old camera headlight is kept as a weaker fill so faces turned away from the sun stay readable
+    var fill = max(dot(N, V), 0.0001) * shader_globals.fill_light;
+    var sun = max(dot(N, L), 0.0) * shadow * shader_globals.sun_color;
+    var glow = glow_lighting(unbend(in.world_position.xyz), N) * shader_globals.glow_intensity;
+    var diff = (fill + sun + glow) * color * texCol.rgb;
+
+#ifdef BLOCK_ATLAS
+    // Move into the block to find the one this face belongs to, so each block pulses on its own
+    let block = round(unbend(in.world_position.xyz) - N * 0.25);
+    let pulse = glow_pulse(layer_emissive.a, dot(block, vec3<f32>(0.7, 1.3, 0.9)));
+    diff += layer_emissive.rgb * texCol.rgb * pulse * shader_globals.glow_intensity;
+    diff = apply_damage(diff, in.uv, find_block_damage(vec3<i32>(block)));
+#else
+    let pulse = glow_pulse(material.emissive_pulse, dot(mesh.model[3].xyz, vec3<f32>(0.7, 1.3, 0.9)));
+    diff += material.emissive * texCol.rgb * pulse * shader_globals.glow_intensity;
+#endif
 
     var vig = vignette(viewport.xy);
-    var result = mix(ambient + diff, vec3<f32>(0.02, 0.02, 0.01), fog) * vig;
+    var result = mix(ambient + diff, shader_globals.fog_color, fog) * vig;
 
-    return vec4(result, 1.0);
+    return vec4(result, alpha);
 
 #endif
 }
\ No newline at end of file
@@ -0,0 +1,81 @@
+#import bevy_sprite::mesh2d_view_bindings
+
+struct PostProcessSettings {
+    daylight: f32,
+    weather_amount: f32,
+    film_grain: f32,
+    chromatic_aberration: f32,
+    time: f32,
+};
+
+@group(1) @binding(0)
+var source_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var source_sampler: sampler;
+@group(1) @binding(2)
+var day_lut: texture_2d<f32>;
+@group(1) @binding(3)
+var day_lut_sampler: sampler;
+@group(1) @binding(4)
+var night_lut: texture_2d<f32>;
+@group(1) @binding(5)
+var night_lut_sampler: sampler;
+@group(1) @binding(6)
+var weather_lut: texture_2d<f32>;
+@group(1) @binding(7)
+var weather_lut_sampler: sampler;
+@group(1) @binding(8)
+var<uniform> settings: PostProcessSettings;
+
+// LUTs are 16 slices of 16x16 pixels side by side, red along x, green along y and blue picks the slice
+let LUT_SIZE: f32 = 16.0;
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let c = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+    let low = c * 12.92;
+    let high = 1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, c <= vec3<f32>(0.0031308));
+}
+
+fn lut_uv(rg: vec2<f32>, slice: f32) -> vec2<f32> {
+    let pixel = rg * (LUT_SIZE - 1.0) + 0.5;
+    return vec2<f32>((slice * LUT_SIZE + pixel.x) / (LUT_SIZE * LUT_SIZE), pixel.y / LUT_SIZE);
+}
+
+// Takes an sRGB colour, the LUT texture is sRGB too so the result is linear again.
+// Bilinear filtering covers red and green, blue is interpolated between the two nearest slices.
+fn sample_lut(lut: texture_2d<f32>, lut_sampler: sampler, color: vec3<f32>) -> vec3<f32> {
+    let blue = color.b * (LUT_SIZE - 1.0);
+    let slice = floor(blue);
+    let next = min(slice + 1.0, LUT_SIZE - 1.0);
+    let a = textureSample(lut, lut_sampler, lut_uv(color.rg, slice)).rgb;
+    let b = textureSample(lut, lut_sampler, lut_uv(color.rg, next)).rgb;
+    return mix(a, b, blue - slice);
+}
+
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
+@fragment
+fn fragment(
+    #import bevy_sprite::mesh2d_vertex_output
+) -> @location(0) vec4<f32> {
+    // Red and blue are pulled apart towards the screen edges
+    let offset = (uv - 0.5) * settings.chromatic_aberration;
+    let center = textureSample(source_texture, source_sampler, uv);
+    let red = textureSample(source_texture, source_sampler, uv + offset).r;
+    let blue = textureSample(source_texture, source_sampler, uv - offset).b;
+    let color = linear_to_srgb(vec3<f32>(red, center.g, blue));
+
+    let night = sample_lut(night_lut, night_lut_sampler, color);
+    let day = sample_lut(day_lut, day_lut_sampler, color);
+    let weather = sample_lut(weather_lut, weather_lut_sampler, color);
+    var graded = mix(mix(night, day, settings.daylight), weather, settings.weather_amount);
+
+    let pixel = floor(uv * view.viewport.zw);
+    let grain = hash(pixel + fract(settings.time) * 100.0) - 0.5;
+    graded = max(graded + grain * settings.film_grain, vec3<f32>(0.0));
+
+    return vec4<f32>(graded, 1.0);
+}
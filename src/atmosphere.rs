@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::load_config, day_night::DayNight, shaders::ShaderGlobals, weather::Weather, Player};
+
+// Half size of the area around the player that is covered by the shadow map
+const SHADOW_EXTENT: f32 = 25.0;
+
+// Daytime values, DayNight and Weather change them over time
+#[derive(Resource, Reflect, Serialize, Deserialize, Clone)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct Atmosphere {
+    pub fog_min: f32,
+    pub fog_max: f32,
+    pub fog_color: Color,
+    pub ambient: Color,
+    pub vignette_radius: f32,
+    pub vignette_softness: f32,
+    pub light_direction: Vec3,
+    pub sun_color: Color,
+    pub fill_light: f32,
+    pub shadows: bool,
+}
+
+#[derive(Component)]
+struct Sun;
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Self {
+            fog_min: 0.0,
+            fog_max: 9.0,
+            fog_color: Color::rgb(0.02, 0.02, 0.01),
+            ambient: Color::rgb(0.01, 0.01, 0.01),
+            vignette_radius: 0.7,
+            vignette_softness: 0.35,
+            light_direction: Vec3::new(0.5, -0.7, 0.2),
+            sun_color: Color::rgb(0.5, 0.47, 0.4),
+            fill_light: 0.7,
+            shadows: true,
+        }
+    }
+}
+
+fn color_to_vec3(color: Color) -> Vec3 {
+    Vec3::new(color.r(), color.g(), color.b())
+}
+
+fn atmosphere_system(
+    atmosphere: Res<Atmosphere>,
+    day_night: Res<DayNight>,
+    weather: Res<Weather>,
+    mut globals: ResMut<ShaderGlobals>,
+) {
+    let fog_distance = day_night.blend(day_night.night_fog_distance, 1.0) * weather.fog_distance();
+    let sunlight = day_night.blend(day_night.night_sunlight, 1.0) * weather.sunlight();
+
+    globals.fog_min = atmosphere.fog_min * fog_distance;
+    globals.fog_max = atmosphere.fog_max * fog_distance;
+    globals.fog_color = color_to_vec3(weather.tint_fog(atmosphere.fog_color));
+    globals.ambient = color_to_vec3(day_night.blend_color(day_night.night_ambient, atmosphere.ambient));
+    globals.vignette_radius = atmosphere.vignette_radius;
+    globals.vignette_softness = atmosphere.vignette_softness;
+    globals.light_direction = atmosphere.light_direction.normalize_or_zero();
+    globals.sun_color = color_to_vec3(atmosphere.sun_color) * sunlight;
+    globals.fill_light = atmosphere.fill_light;
+    globals.glow_intensity = day_night.blend(day_night.night_glow, day_night.day_glow);
+}
+
+fn spawn_sun(mut commands: Commands, atmosphere: Res<Atmosphere>) {
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                shadows_enabled: atmosphere.shadows,
+                shadow_projection: OrthographicProjection {
+                    left: -SHADOW_EXTENT,
+                    right: SHADOW_EXTENT,
+                    bottom: -SHADOW_EXTENT,
+                    top: SHADOW_EXTENT,
+                    near: -2.0 * SHADOW_EXTENT,
+                    far: 2.0 * SHADOW_EXTENT,
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        },
+        Sun,
+        Name::new("Sun"),
+    ));
+}
+
+// The shader takes the light direction from the atmosphere, the sun entity only provides the shadow map
+fn sun_system(
+    atmosphere: Res<Atmosphere>,
+    player_query: Query<&Transform, (With<Player>, Without<Sun>)>,
+    mut sun_query: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+) {
+    let Ok(player_t) = player_query.get_single() else {
+        return;
+    };
+    let direction = atmosphere.light_direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return;
+    }
+    let up = if direction.cross(Vec3::Y).length_squared() < 0.0001 { Vec3::Z } else { Vec3::Y };
+
+    for (mut transform, mut light) in sun_query.iter_mut() {
+        *transform = Transform::from_translation(player_t.translation)
+            .looking_at(player_t.translation + direction, up);
+        if light.shadows_enabled != atmosphere.shadows {
+            light.shadows_enabled = atmosphere.shadows;
+        }
+    }
+}
+
+pub struct AtmospherePlugin;
+
+impl Plugin for AtmospherePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_config::<Atmosphere>("atmosphere.ron"))
+            .register_type::<Atmosphere>() // Only needed for in-game inspector
+            .add_startup_system(spawn_sun)
+            .add_system(atmosphere_system)
+            .add_system(sun_system);
+    }
+}
@@ -0,0 +1,79 @@
+use bevy::{prelude::*, transform::TransformSystem};
+
+use crate::{
+    shaders::{CustomMaterial, MaterialVariants},
+    MainCamera,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum BillboardMode {
+    // Faces the camera plane completely
+    Spherical,
+    // Only turns around the vertical axis, so the sprite stays upright
+    #[default]
+    Cylindrical,
+}
+
+// Keeps a sprite quad made from plane_mesh facing the MainCamera.
+// The quad is bent by the world bending as a whole, from its origin, so it does not skew.
+#[derive(Component, Default)]
+pub struct Billboard {
+    pub mode: BillboardMode,
+}
+
+impl Billboard {
+    pub fn new(mode: BillboardMode) -> Self {
+        Self { mode }
+    }
+}
+
+// plane_mesh lies in XZ with its normal along +Y and the texture top towards +Z
+fn billboard_rotation(normal: Vec3, up: Vec3) -> Quat {
+    let z_axis = up;
+    let y_axis = normal;
+    let x_axis = y_axis.cross(z_axis);
+    Quat::from_mat3(&Mat3::from_cols(x_axis, y_axis, z_axis))
+}
+
+fn billboard_system(
+    camera_query: Query<&Transform, (With<MainCamera>, Without<Billboard>)>,
+    mut query: Query<(&Billboard, &mut Transform)>,
+) {
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+
+    // Billboards face the camera plane instead of the camera position,
+    // so sprites near the screen edges are not turned sideways
+    let spherical = billboard_rotation(camera_transform.back(), camera_transform.up());
+    let flat_back = Vec3::new(camera_transform.back().x, 0.0, camera_transform.back().z);
+    let cylindrical = billboard_rotation(flat_back.try_normalize().unwrap_or(Vec3::Z), Vec3::Y);
+
+    for (billboard, mut transform) in query.iter_mut() {
+        transform.rotation = match billboard.mode {
+            BillboardMode::Spherical => spherical,
+            BillboardMode::Cylindrical => cylindrical,
+        };
+    }
+}
+
+pub fn billboard_material_system(
+    mut query: Query<&mut Handle<CustomMaterial>, Added<Billboard>>,
+    mut custom_materials: ResMut<Assets<CustomMaterial>>,
+    mut variants: Local<MaterialVariants>,
+) {
+    for mut handle in query.iter_mut() {
+        variants.apply(&mut handle, &mut custom_materials, |mat| mat.bend_from_origin = true);
+    }
+}
+
+pub struct BillboardPlugin;
+
+impl Plugin for BillboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(billboard_material_system).add_system_to_stage(
+            CoreStage::PostUpdate,
+            billboard_system.before(TransformSystem::TransformPropagate),
+        );
+    }
+}
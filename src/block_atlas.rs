@@ -0,0 +1,158 @@
+use bevy::{
+    asset::LoadState,
+    prelude::*,
+    render::render_resource::{
+        Extent3d, TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension,
+    },
+    utils::HashMap,
+};
+use serde::Deserialize;
+
+use crate::{
+    config::load_config,
+    shaders::{BlockMaterial, MAX_BLOCK_LAYERS},
+    RootResource,
+};
+
+#[derive(Deserialize, Default)]
+struct AtlasManifest {
+    layers: Vec<AtlasLayer>,
+}
+
+#[derive(Deserialize)]
+struct AtlasLayer {
+    name: String,
+    image: String,
+    #[serde(default = "default_layer_color")]
+    color: Color,
+    // Light given off by the layer, also cast onto nearby surfaces
+    #[serde(default = "default_emissive")]
+    emissive: Color,
+    // How much the emissive light dims and brightens over time, from 0 to 1
+    #[serde(default)]
+    pulse: f32,
+}
+
+fn default_layer_color() -> Color {
+    Color::WHITE
+}
+
+fn default_emissive() -> Color {
+    Color::BLACK
+}
+
+// All block textures stacked into one texture array, so every chunk mesh can share one material
+#[derive(Resource, Default)]
+pub struct BlockAtlas {
+    layers: HashMap<String, u32>,
+    colors: [Vec4; MAX_BLOCK_LAYERS],
+    emissive: [Vec4; MAX_BLOCK_LAYERS],
+    images: Vec<Handle<Image>>,
+    pub material: Option<Handle<BlockMaterial>>,
+}
+
+impl BlockAtlas {
+    // None for names missing from block_atlas.ron, those are warned about when it is loaded
+    pub fn layer(&self, name: &str) -> Option<u32> {
+        self.layers.get(name).copied()
+    }
+
+    // Emissive colour in rgb and pulse amount in a, zero for unknown names
+    pub fn emissive(&self, name: &str) -> Vec4 {
+        self.layer(name).map_or(Vec4::ZERO, |layer| self.emissive[layer as usize])
+    }
+}
+
+fn load_block_atlas(mut atlas: ResMut<BlockAtlas>, asset_server: Res<AssetServer>) {
+    let manifest: AtlasManifest = load_config("block_atlas.ron");
+    if manifest.layers.len() > MAX_BLOCK_LAYERS {
+        warn!("Block atlas has more than {MAX_BLOCK_LAYERS} layers, the rest are ignored");
+    }
+
+    for (i, layer) in manifest.layers.into_iter().take(MAX_BLOCK_LAYERS).enumerate() {
+        atlas.layers.insert(layer.name, i as u32);
+        atlas.colors[i] = Vec4::new(layer.color.r(), layer.color.g(), layer.color.b(), 1.0);
+        atlas.emissive[i] = Vec4::new(layer.emissive.r(), layer.emissive.g(), layer.emissive.b(), layer.pulse);
+        atlas.images.push(asset_server.load(layer.image.as_str()));
+    }
+    for resource in RootResource::ALL {
+        if atlas.layer(resource.name()).is_none() {
+            warn!("Block atlas has no {} layer, those blocks are not drawn", resource.name());
+        }
+    }
+}
+
+fn build_block_atlas_system(
+    mut atlas: ResMut<BlockAtlas>,
+    mut images: ResMut<Assets<Image>>,
+    mut block_materials: ResMut<Assets<BlockMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    if atlas.material.is_some() || atlas.images.is_empty() {
+        return;
+    }
+    // Images that failed are replaced below, so only wait for the ones still loading
+    let loading = atlas.images.iter().any(|handle| {
+        !matches!(asset_server.get_load_state(handle.id), LoadState::Loaded | LoadState::Failed)
+    });
+    if loading {
+        return;
+    }
+
+    // Layers take the size of the first image that loaded, or 1x1 if none did
+    let first = atlas.images.iter().find_map(|handle| images.get(handle));
+    let (size, format, layer_len) = match first {
+        Some(first) => (first.texture_descriptor.size, first.texture_descriptor.format, first.data.len()),
+        None => (Extent3d { width: 1, height: 1, depth_or_array_layers: 1 }, TextureFormat::Rgba8UnormSrgb, 4),
+    };
+
+    let mut data = Vec::with_capacity(layer_len * atlas.images.len());
+    for handle in atlas.images.iter() {
+        let Some(image) = images.get(handle) else {
+            let path = asset_server.get_handle_path(handle.id).map(|path| path.path().display().to_string());
+            error!("Block atlas image {} failed to load, using a plain layer", path.unwrap_or_default());
+            // White, so the layer colour still tells the blocks apart
+            data.resize(data.len() + layer_len, 255);
+            continue;
+        };
+        if image.texture_descriptor.size != size || image.texture_descriptor.format != format {
+            error!("Block atlas images must all have the same size and format as the first layer");
+            data.resize(data.len() + layer_len, 0);
+        } else {
+            data.extend_from_slice(&image.data);
+        }
+    }
+
+    let layers = atlas.images.len() as u32;
+    let mut array = Image::new(
+        Extent3d {
+            width: size.width,
+            height: size.height * layers,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        format,
+    );
+    array.reinterpret_stacked_2d_as_array(layers);
+    array.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..default()
+    });
+
+    atlas.material = Some(block_materials.add(BlockMaterial {
+        layer_colors: atlas.colors,
+        layer_emissive: atlas.emissive,
+        texture: images.add(array),
+    }));
+}
+
+pub struct BlockAtlasPlugin;
+
+impl Plugin for BlockAtlasPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(BlockAtlas::default())
+            .add_startup_system(load_block_atlas)
+            .add_system(build_block_atlas_system);
+    }
+}
@@ -0,0 +1,302 @@
+use bevy::{
+    pbr::NotShadowCaster,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+use crate::{
+    curvature::WorldCurvature,
+    input::{Action, ActionState},
+    inventory::{Inventory, Item, Items},
+    shaders::CustomMaterial,
+    vec3i::Vec3i,
+    world_generation::spawn_root,
+    AudioHandles, BlockMap, MainCamera, Player, Root, RootResource,
+};
+
+// Root id of blocks placed by the player, generated roots count up from zero
+const PLACED_ROOT_ID: i64 = -1;
+// Blocks can be placed at most this far from the player
+const BUILD_REACH: f32 = 4.0;
+const CURSOR_STEP: f32 = 0.1;
+const CURSOR_DISTANCE: f32 = 40.0;
+// Highest cell above the facing target that is tried when the target is taken
+const MAX_STACK: i64 = 3;
+const GHOST_ALPHA: u8 = 110;
+
+#[derive(Resource, Default)]
+pub struct BuildMode {
+    pub active: bool,
+    // Index among the items that place blocks
+    selected: usize,
+    // Free cell the next block goes to
+    target: Option<Vec3i>,
+}
+
+impl BuildMode {
+    pub fn selected_item<'a>(&self, items: &'a Items) -> Option<&'a Item> {
+        let count = items.iter().filter(|item| item.block.is_some()).count();
+        items.iter().filter(|item| item.block.is_some()).nth(self.selected % count.max(1))
+    }
+}
+
+// Translucent cube showing where the next block goes. It is drawn by the same shader as the
+// blocks, so it bends with the world and lines up with the chunk meshes.
+#[derive(Component)]
+struct BuildGhost;
+
+#[derive(Component)]
+struct BuildHudText;
+
+#[derive(Resource)]
+struct GhostMaterial(Handle<CustomMaterial>);
+
+fn ghost_color(resource: Option<RootResource>, placeable: bool) -> Color {
+    match resource.filter(|_| placeable) {
+        Some(RootResource::Sap) => Color::rgb(1.0, 0.6, 0.2),
+        Some(RootResource::Bark) => Color::rgb(0.55, 0.4, 0.25),
+        Some(RootResource::Wood) => Color::rgb(0.8, 0.65, 0.4),
+        None => Color::rgb(1.0, 0.15, 0.1),
+    }
+}
+
+fn setup_build_mode(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
+    mut custom_materials: ResMut<Assets<CustomMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    // The ghost takes its transparency from the texture
+    let texture = images.add(Image::new_fill(
+        Extent3d { width: 1, height: 1, ..default() },
+        TextureDimension::D2,
+        &[255, 255, 255, GHOST_ALPHA],
+        TextureFormat::Rgba8UnormSrgb,
+    ));
+    let material = custom_materials.add(
+        CustomMaterial::new(ghost_color(None, false), &texture).with_alpha_mode(AlphaMode::Blend),
+    );
+
+    commands.spawn((
+        MaterialMeshBundle {
+            mesh: meshes.add(Mesh::from(shape::Cube { size: 1.02 })),
+            material: material.clone(),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        },
+        NotShadowCaster,
+        BuildGhost,
+        Name::new("BuildGhost"),
+    ));
+    commands.insert_resource(GhostMaterial(material));
+
+    // Above the tool name
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("monogram.ttf"),
+                font_size: 30.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_style(Style {
+            margin: UiRect::all(Val::Px(5.0)),
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(10.0),
+                bottom: Val::Px(40.0),
+                ..default()
+            },
+            ..default()
+        }),
+        BuildHudText,
+    ));
+}
+
+fn cell_of(position: Vec3) -> Vec3i {
+    let cell = position.round();
+    Vec3i::new(cell.x as i64, cell.y as i64, cell.z as i64)
+}
+
+// Blocks are never placed where they would push into the player's ball collider
+fn overlaps_player(cell: Vec3i, player_position: Vec3) -> bool {
+    let outside = ((player_position - Vec3::from(cell)).abs() - Vec3::splat(0.5)).max(Vec3::ZERO);
+    outside.length() < 0.5
+}
+
+// Free cell in front of the block under the cursor. The cursor ray is followed through the
+// drawn, bent world and mapped back to world positions, like in occlusion.
+fn cursor_target(
+    cursor: Vec2,
+    window: &Window,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    curvature: &WorldCurvature,
+    blockmap: &BlockMap,
+) -> Option<Vec3i> {
+    let ndc = cursor / Vec2::new(window.width(), window.height()) * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    // Reverse z, the near plane is at 1.0
+    let near = ndc_to_world.project_point3(ndc.extend(1.0));
+    let direction = (ndc_to_world.project_point3(ndc.extend(0.5)) - near).try_normalize()?;
+
+    let camera_position = camera_transform.translation();
+    let mut previous = None;
+    for i in 0..(CURSOR_DISTANCE / CURSOR_STEP) as usize {
+        let world = curvature.unbend(near + direction * (i as f32 * CURSOR_STEP), camera_position);
+        let cell = cell_of(world);
+        // Ground blocks are only a plane with one big collider, so hitting the ground counts as a block
+        if blockmap.entities.contains_key(&cell) || world.y < -0.5 {
+            return previous;
+        }
+        previous = Some(cell);
+    }
+    None
+}
+
+// Next to the player in the facing direction, stacked on top when that cell is taken
+fn facing_target(player_position: Vec3, facing: Vec3, blockmap: &BlockMap) -> Option<Vec3i> {
+    let front = cell_of(player_position + facing);
+    (0..=MAX_STACK)
+        .map(|up| front + Vec3i::new(0, up, 0))
+        .find(|cell| !blockmap.entities.contains_key(cell))
+}
+
+fn build_target_system(
+    mut build_mode: ResMut<BuildMode>,
+    actions: Res<ActionState>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    player_query: Query<(&Transform, &Player)>,
+    curvature: Res<WorldCurvature>,
+    blockmap: Res<BlockMap>,
+) {
+    if actions.just_pressed(Action::Build) {
+        build_mode.active = !build_mode.active;
+    }
+    if build_mode.active && actions.just_pressed(Action::NextTool) {
+        // Wrapped around by the number of block items in selected_item
+        build_mode.selected = build_mode.selected.wrapping_add(1);
+    }
+    if !build_mode.active {
+        build_mode.target = None;
+        return;
+    }
+
+    let Ok((player_transform, player)) = player_query.get_single() else {
+        return;
+    };
+    let player_position = player_transform.translation;
+
+    let cursor = windows.get_primary().and_then(|window| {
+        let (camera, camera_transform) = camera_query.get_single().ok()?;
+        let cursor = window.cursor_position()?;
+        cursor_target(cursor, window, camera, camera_transform, &curvature, &blockmap)
+    });
+    let target = cursor
+        .filter(|cell| Vec3::from(*cell).distance(player_position) <= BUILD_REACH)
+        .or_else(|| facing_target(player_position, player.facing.vector(), &blockmap))
+        .filter(|cell| cell.y() >= 0 && !overlaps_player(*cell, player_position));
+
+    if build_mode.target != target {
+        build_mode.target = target;
+    }
+}
+
+fn place_block_system(
+    mut commands: Commands,
+    build_mode: Res<BuildMode>,
+    actions: Res<ActionState>,
+    mut inventory_query: Query<&mut Inventory, With<Player>>,
+    items: Res<Items>,
+    mut blockmap: ResMut<BlockMap>,
+    audio_handles: Res<AudioHandles>,
+    audio: Res<Audio>,
+) {
+    if !build_mode.active || !actions.just_pressed(Action::Attack) {
+        return;
+    }
+    let (Some(target), Ok(mut inventory)) = (build_mode.target, inventory_query.get_single_mut()) else {
+        return;
+    };
+    let Some(item) = build_mode.selected_item(&items) else {
+        return;
+    };
+    let Some(resource) = item.block else {
+        return;
+    };
+    if inventory.remove(&item.id, 1) == 0 {
+        return;
+    }
+
+    // Placed blocks fall and collapse like the generated ones, and give one of their resource back when mined
+    let root = Root { id: PLACED_ROOT_ID, resource, mineable: 1 };
+    spawn_root(&target, root, &mut blockmap, &mut commands);
+    audio.play(audio_handles.block(resource));
+}
+
+fn build_ghost_system(
+    build_mode: Res<BuildMode>,
+    inventory_query: Query<&Inventory, With<Player>>,
+    items: Res<Items>,
+    mut ghost_query: Query<(&mut Transform, &mut Visibility), With<BuildGhost>>,
+    ghost_material: Res<GhostMaterial>,
+    mut custom_materials: ResMut<Assets<CustomMaterial>>,
+) {
+    for (mut transform, mut visibility) in ghost_query.iter_mut() {
+        visibility.is_visible = build_mode.target.is_some();
+        if let Some(target) = build_mode.target {
+            transform.translation = target.into();
+        }
+    }
+
+    let item = build_mode.selected_item(&items);
+    let placeable = item
+        .zip(inventory_query.get_single().ok())
+        .map_or(false, |(item, inventory)| inventory.count(&item.id) > 0);
+    let color = ghost_color(item.and_then(|item| item.block), placeable);
+    let color = Vec3::new(color.r(), color.g(), color.b());
+    if custom_materials.get(&ghost_material.0).map_or(false, |mat| mat.color != color) {
+        if let Some(mat) = custom_materials.get_mut(&ghost_material.0) {
+            mat.color = color;
+        }
+    }
+}
+
+fn build_hud_system(
+    build_mode: Res<BuildMode>,
+    inventory_query: Query<&Inventory, With<Player>>,
+    items: Res<Items>,
+    mut text_query: Query<&mut Text, With<BuildHudText>>,
+) {
+    let Ok(inventory) = inventory_query.get_single() else {
+        return;
+    };
+    let hud = match build_mode.selected_item(&items).filter(|_| build_mode.active) {
+        Some(item) => format!("Building {} ({})", item.name, inventory.count(&item.id)),
+        None if build_mode.active => "No blocks to build".to_string(),
+        None => String::new(),
+    };
+    for mut text in text_query.iter_mut() {
+        if let Some(section) = text.sections.first_mut() {
+            if section.value != hud {
+                section.value = hud.clone();
+            }
+        }
+    }
+}
+
+pub struct BuildModePlugin;
+
+impl Plugin for BuildModePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BuildMode>()
+            .add_startup_system(setup_build_mode)
+            .add_system(build_target_system)
+            .add_system(place_block_system.after(build_target_system))
+            .add_system(build_ghost_system.after(place_block_system))
+            .add_system(build_hud_system.after(place_block_system));
+    }
+}
@@ -0,0 +1,280 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+    utils::HashMap,
+};
+
+use crate::{
+    block_atlas::BlockAtlas,
+    culling::BendCulling,
+    shaders::{BlockDamage, BlockDamageEntry, ATTRIBUTE_BLOCK_LAYER},
+    vec3i::Vec3i,
+    BlockMap, BlockPosition, Health, Root,
+};
+
+pub const CHUNK_SIZE: i64 = 16;
+
+const N: usize = CHUNK_SIZE as usize;
+
+#[derive(Resource, Default)]
+struct ChunkMeshes {
+    entities: HashMap<Vec3i, (Entity, Handle<Mesh>)>,
+}
+
+pub fn chunk_of(position: Vec3i) -> Vec3i {
+    Vec3i::new(
+        position.x().div_euclid(CHUNK_SIZE),
+        position.y().div_euclid(CHUNK_SIZE),
+        position.z().div_euclid(CHUNK_SIZE),
+    )
+}
+
+#[derive(Default)]
+struct MeshBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    layers: Vec<u32>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    fn add_quad(&mut self, corners: [Vec3; 4], normal: Vec3, size: Vec2, layer: u32, flip: bool) {
+        let start = self.positions.len() as u32;
+        let uvs = [[0.0, size.y], [size.x, size.y], [size.x, 0.0], [0.0, 0.0]];
+        for (corner, uv) in corners.iter().zip(uvs) {
+            self.positions.push(corner.to_array());
+            self.normals.push(normal.to_array());
+            self.uvs.push(uv);
+            self.layers.push(layer);
+        }
+        let order = if flip { [0, 2, 1, 0, 3, 2] } else { [0, 1, 2, 0, 2, 3] };
+        self.indices.extend(order.iter().map(|i| start + i));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.insert_attribute(ATTRIBUTE_BLOCK_LAYER, self.layers);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+// Atlas layer of every block in the chunk. Faces are only merged when their layers match,
+// damage is drawn from BlockDamage.
+// Returns None if some block in the chunk has not been fully spawned yet.
+fn chunk_faces(
+    blockmap: &BlockMap,
+    atlas: &BlockAtlas,
+    block_query: &Query<&Root>,
+    origin: Vec3i,
+) -> Option<Box<[[[Option<u32>; N]; N]; N]>> {
+    let mut faces = Box::new([[[None; N]; N]; N]);
+    for x in 0..N {
+        for y in 0..N {
+            for z in 0..N {
+                let position = origin + Vec3i::new(x as i64, y as i64, z as i64);
+                if let Some(entity) = blockmap.entities.get(&position) {
+                    let root = block_query.get(*entity).ok()?;
+                    faces[x][y][z] = atlas.layer(root.resource.name());
+                }
+            }
+        }
+    }
+    Some(faces)
+}
+
+fn build_chunk(
+    blockmap: &BlockMap,
+    atlas: &BlockAtlas,
+    block_query: &Query<&Root>,
+    chunk: Vec3i,
+) -> Option<MeshBuilder> {
+    let origin = Vec3i::new(chunk.x() * CHUNK_SIZE, chunk.y() * CHUNK_SIZE, chunk.z() * CHUNK_SIZE);
+    let faces = chunk_faces(blockmap, atlas, block_query, origin)?;
+    let mut builder = MeshBuilder::default();
+
+    for d in 0..3 {
+        let u_axis = (d + 1) % 3;
+        let v_axis = (d + 2) % 3;
+
+        for sign in [-1i64, 1] {
+            let mut step = [0i64; 3];
+            step[d] = sign;
+            let normal_offset = Vec3i::new(step[0], step[1], step[2]);
+            let normal = Vec3::from(normal_offset);
+
+            for slice in 0..N {
+                // Collect the faces of this slice that are not hidden by a neighbouring block
+                let mut mask = [[None; N]; N];
+                for u in 0..N {
+                    for v in 0..N {
+                        let mut local = [0usize; 3];
+                        local[d] = slice;
+                        local[u_axis] = u;
+                        local[v_axis] = v;
+
+                        let Some(layer) = faces[local[0]][local[1]][local[2]] else {
+                            continue;
+                        };
+                        let position = origin + Vec3i::new(local[0] as i64, local[1] as i64, local[2] as i64);
+
+                        // Bottom faces of the lowest blocks are always covered by the ground
+                        if normal_offset.y() < 0 && position.y() == 0 {
+                            continue;
+                        }
+                        if blockmap.entities.contains_key(&(position + normal_offset)) {
+                            continue;
+                        }
+                        mask[u][v] = Some(layer);
+                    }
+                }
+
+                // Greedily merge equal faces into rectangles
+                for v in 0..N {
+                    let mut u = 0;
+                    while u < N {
+                        let Some(layer) = mask[u][v] else {
+                            u += 1;
+                            continue;
+                        };
+
+                        let mut width = 1;
+                        while u + width < N && mask[u + width][v] == Some(layer) {
+                            width += 1;
+                        }
+                        let mut height = 1;
+                        'grow: while v + height < N {
+                            for k in 0..width {
+                                if mask[u + k][v + height] != Some(layer) {
+                                    break 'grow;
+                                }
+                            }
+                            height += 1;
+                        }
+                        for du in 0..width {
+                            for dv in 0..height {
+                                mask[u + du][v + dv] = None;
+                            }
+                        }
+
+                        let corner = |cu: usize, cv: usize| {
+                            let mut p = [0.0f32; 3];
+                            p[d] = slice as f32 + 0.5 * sign as f32;
+                            p[u_axis] = cu as f32 - 0.5;
+                            p[v_axis] = cv as f32 - 0.5;
+                            Vec3::from(origin) + Vec3::from(p)
+                        };
+                        builder.add_quad(
+                            [
+                                corner(u, v),
+                                corner(u + width, v),
+                                corner(u + width, v + height),
+                                corner(u, v + height),
+                            ],
+                            normal,
+                            Vec2::new(width as f32, height as f32),
+                            layer,
+                            sign < 0,
+                        );
+
+                        u += width;
+                    }
+                }
+            }
+        }
+    }
+
+    Some(builder)
+}
+
+fn rebuild_chunk_meshes_system(
+    mut blockmap: ResMut<BlockMap>,
+    mut chunk_meshes: ResMut<ChunkMeshes>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    atlas: Res<BlockAtlas>,
+    block_query: Query<&Root>,
+    mut commands: Commands,
+) {
+    // Chunks stay dirty until the atlas material has been built
+    let Some(material) = atlas.material.clone() else {
+        return;
+    };
+
+    let dirty: Vec<Vec3i> = blockmap.dirty_chunks.drain().collect();
+    for chunk in dirty {
+        let Some(builder) = build_chunk(&blockmap, &atlas, &block_query, chunk) else {
+            // Try again next frame when the spawn commands have been applied
+            blockmap.dirty_chunks.insert(chunk);
+            continue;
+        };
+
+        let existing = chunk_meshes.entities.get(&chunk).map(|(_, handle)| handle.clone());
+        match (builder.is_empty(), existing) {
+            (false, Some(handle)) => {
+                if let Some(mesh) = meshes.get_mut(&handle) {
+                    *mesh = builder.build();
+                }
+            }
+            (false, None) => {
+                let handle = meshes.add(builder.build());
+                let entity = commands
+                    .spawn((
+                        MaterialMeshBundle {
+                            mesh: handle.clone(),
+                            material: material.clone(),
+                            ..default()
+                        },
+                        BendCulling::default(),
+                        Name::new("Chunk"),
+                    ))
+                    .id();
+                chunk_meshes.entities.insert(chunk, (entity, handle));
+            }
+            (true, Some(_)) => {
+                if let Some((entity, _)) = chunk_meshes.entities.remove(&chunk) {
+                    commands.entity(entity).despawn();
+                }
+            }
+            (true, None) => {}
+        }
+    }
+}
+
+// Collapsing blocks take their damage along, so the list is rebuilt from the blocks themselves
+fn block_damage_system(
+    changed_query: Query<(), (With<Root>, Or<(Changed<Health>, Changed<BlockPosition>)>)>,
+    removed: RemovedComponents<Health>,
+    block_query: Query<(&BlockPosition, &Health)>,
+    mut block_damage: ResMut<BlockDamage>,
+) {
+    if changed_query.is_empty() && removed.iter().next().is_none() {
+        return;
+    }
+    block_damage.blocks = block_query
+        .iter()
+        .filter(|(_, health)| health.damage_ratio() > 0.0)
+        .map(|(position, health)| BlockDamageEntry {
+            position: IVec3::new(position.0.x() as i32, position.0.y() as i32, position.0.z() as i32),
+            damage: health.damage_ratio(),
+            last_hit: health.last_hit,
+        })
+        .collect();
+}
+
+pub struct ChunkMeshPlugin;
+
+impl Plugin for ChunkMeshPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ChunkMeshes::default())
+            .add_system_to_stage(CoreStage::PostUpdate, rebuild_chunk_meshes_system)
+            .add_system_to_stage(CoreStage::PostUpdate, block_damage_system);
+    }
+}
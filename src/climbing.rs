@@ -0,0 +1,121 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::load_config,
+    grounded::Grounded,
+    input::ActionState,
+    BlockMap, Root, RootResource,
+};
+
+#[derive(Resource, Reflect, Serialize, Deserialize, Clone)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct ClimbSettings {
+    // Upward speed when pushing fully into a wall
+    pub climb_speed: f32,
+    // Seconds of climbing on full stamina
+    pub climb_time: f32,
+    // Seconds on the ground to recover from empty to full stamina
+    pub recovery_time: f32,
+    // Stamina needed to start climbing, so an empty climber does not flicker on and off the wall
+    pub min_start_stamina: f32,
+    // Distance from the centre at which walls are looked for
+    pub reach: f32,
+    // How much of the movement has to point into the wall
+    pub min_push: f32,
+}
+
+impl Default for ClimbSettings {
+    fn default() -> Self {
+        Self {
+            climb_speed: 2.5,
+            climb_time: 3.0,
+            recovery_time: 2.0,
+            min_start_stamina: 0.25,
+            reach: 0.6,
+            min_push: 0.5,
+        }
+    }
+}
+
+// Lets an entity climb Bark and Wood blocks by moving into them
+#[derive(Component)]
+pub struct Climber {
+    // 1.0 is full, climbing empties it in ClimbSettings::climb_time
+    pub stamina: f32,
+    pub climbing: bool,
+}
+
+impl Default for Climber {
+    fn default() -> Self {
+        Self { stamina: 1.0, climbing: false }
+    }
+}
+
+fn is_climbable(resource: RootResource) -> bool {
+    matches!(resource, RootResource::Bark | RootResource::Wood)
+}
+
+// How hard the movement pushes into a climbable block next to the position, zero when there is none.
+// Both horizontal axes are checked so walls are found when moving diagonally too.
+fn wall_push(
+    position: Vec3,
+    movement: Vec3,
+    settings: &ClimbSettings,
+    blockmap: &BlockMap,
+    root_query: &Query<&Root>,
+) -> f32 {
+    [Vec3::X * movement.x.signum(), Vec3::Z * movement.z.signum()]
+        .into_iter()
+        .filter(|axis| movement.dot(*axis) >= settings.min_push)
+        .filter(|axis| {
+            let cell = (position + *axis * settings.reach).round();
+            blockmap
+                .entities
+                .get(&(cell.x as i64, cell.y as i64, cell.z as i64).into())
+                .and_then(|entity| root_query.get(*entity).ok())
+                .map_or(false, |root| is_climbable(root.resource))
+        })
+        .map(|axis| movement.dot(axis))
+        .fold(0.0, f32::max)
+}
+
+fn climbing_system(
+    mut query: Query<(&Transform, &mut Velocity, &mut Climber, &Grounded)>,
+    root_query: Query<&Root>,
+    blockmap: Res<BlockMap>,
+    actions: Res<ActionState>,
+    settings: Res<ClimbSettings>,
+    time: Res<Time>,
+) {
+    let movement = actions.movement();
+    for (transform, mut velocity, mut climber, grounded) in query.iter_mut() {
+        let push = wall_push(transform.translation, movement, &settings, &blockmap, &root_query);
+
+        climber.climbing = if climber.climbing {
+            push > 0.0 && climber.stamina > 0.0
+        } else {
+            push > 0.0 && climber.stamina >= settings.min_start_stamina
+        };
+
+        if climber.climbing {
+            // Replaces the speed gained from gravity since the last frame
+            velocity.linvel.y = settings.climb_speed * push;
+            climber.stamina = (climber.stamina - time.delta_seconds() / settings.climb_time).max(0.0);
+        } else if grounded.on_ground {
+            climber.stamina = (climber.stamina + time.delta_seconds() / settings.recovery_time).min(1.0);
+        }
+    }
+}
+
+pub struct ClimbingPlugin;
+
+impl Plugin for ClimbingPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_config::<ClimbSettings>("climb.ron"))
+            .register_type::<ClimbSettings>() // Only needed for in-game inspector
+            .add_system(climbing_system);
+    }
+}
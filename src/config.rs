@@ -0,0 +1,75 @@
+use std::{env, fs, io::ErrorKind, path::PathBuf};
+
+use bevy::{asset::FileAssetIo, prelude::*};
+use ron::ser::PrettyConfig;
+use serde::{de::DeserializeOwned, Serialize};
+
+// Settings changed in game are saved in this directory under the user's config directory
+const USER_CONFIG_DIR: &str = "sap_from_the_roots";
+
+pub fn config_path(file: &str) -> PathBuf {
+    FileAssetIo::get_base_path().join("assets").join(file)
+}
+
+// Kept out of assets so saving never overwrites the shipped defaults
+pub fn user_config_path(file: &str) -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    base.map(|base| base.join(USER_CONFIG_DIR).join(file))
+}
+
+// Missing or broken config files fall back to the defaults so the game still starts
+pub fn load_config<T: DeserializeOwned + Default>(file: &str) -> T {
+    let path = config_path(file);
+    match fs::read_to_string(&path) {
+        Ok(text) => ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("Could not parse {}: {}", path.display(), err);
+            T::default()
+        }),
+        Err(err) => {
+            warn!("Could not read {}: {}", path.display(), err);
+            T::default()
+        }
+    }
+}
+
+// The user's saved copy of a config file, None when there is none yet or it cannot be read
+pub fn load_user_config<T: DeserializeOwned>(file: &str) -> Option<T> {
+    let path = user_config_path(file)?;
+    match fs::read_to_string(&path) {
+        Ok(text) => ron::from_str(&text)
+            .map_err(|err| warn!("Could not parse {}: {}", path.display(), err))
+            .ok(),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => {
+            warn!("Could not read {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+// Used for settings changed in game, errors are only logged
+pub fn save_user_config<T: Serialize>(file: &str, value: &T) {
+    let Some(path) = user_config_path(file) else {
+        warn!("No user config directory, {} is not saved", file);
+        return;
+    };
+    let result = ron::ser::to_string_pretty(value, PrettyConfig::default())
+        .map_err(|err| err.to_string())
+        .and_then(|text| {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+            }
+            fs::write(&path, text).map_err(|err| err.to_string())
+        });
+    if let Err(err) = result {
+        warn!("Could not write {}: {}", path.display(), err);
+    }
+}
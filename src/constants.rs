@@ -1,6 +1,6 @@
 use std::f32::consts::PI;
 
-use bevy::{prelude::{KeyCode, Vec3}, utils::HashMap};
+use bevy::prelude::Vec3;
 
 pub const FIELD_OF_VIEW: f32 = 80.0 * (PI / 180.0);
 
@@ -11,16 +11,21 @@ pub const INITIAL_CAMERA_OFFSET: Vec3 = Vec3::new(0.0, 4.0, 4.0);
 
 pub const DEFAULT_BENDING: f32 = 0.03;
 
-// lazy_static is used because HashMap cannot be created at compile time
-lazy_static!{
-    pub static ref KEYS: HashMap<KeyCode, Vec3> = [
-        (KeyCode::Up, (0.0, 0.0, -1.0).into()),
-        (KeyCode::Down, (0.0, 0.0, 1.0).into()),
-        (KeyCode::Left, (-1.0, 0.0, 0.0).into()),
-        (KeyCode::Right, (1.0, 0.0, 0.0).into()),
-        (KeyCode::W, (0.0, 0.0, -1.0).into()),
-        (KeyCode::S, (0.0, 0.0, 1.0).into()),
-        (KeyCode::A, (-1.0, 0.0, 0.0).into()),
-        (KeyCode::D, (1.0, 0.0, 0.0).into()),
-    ].iter().copied().collect();
-}
+// Sprite texels below this alpha are cut out
+pub const SPRITE_ALPHA_CUTOFF: f32 = 0.5;
+
+// How far foliage sprites move with the wind
+pub const BUSH_SWAY: f32 = 0.25;
+pub const BRANCH_SWAY: f32 = 0.5;
+
+// Vertical speed at which the player switches to the jump or fall animation
+pub const AIRBORNE_ANIMATION_SPEED: f32 = 1.0;
+// Horizontal speed at which the player switches from idle to walking
+pub const WALK_ANIMATION_SPEED: f32 = 0.5;
+
+pub const PLAYER_INVENTORY_SLOTS: usize = 16;
+// Seconds the last change to the inventory is shown next to the item count
+pub const HUD_CHANGE_TIME: f32 = 2.0;
+
+// Hit time for blocks that have not been damaged, far enough in the past that no flash is shown
+pub const NEVER_HIT: f32 = -1000.0;
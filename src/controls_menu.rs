@@ -0,0 +1,214 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::RapierConfiguration;
+
+use crate::{
+    config::save_user_config,
+    input::{Action, ActionState, InputMap, INPUT_MAP_FILE},
+};
+
+// Pause screen that lists the bindings of every action and lets them be changed.
+// Menu navigation uses fixed keys and buttons so a bad binding cannot lock the player out.
+#[derive(Resource, Default)]
+struct ControlsMenu {
+    open: bool,
+    selected: usize,
+    // Set while the next key press is bound to the selected action
+    waiting: bool,
+    status: String,
+}
+
+#[derive(Component)]
+struct ControlsMenuRoot;
+
+#[derive(Component)]
+struct ControlsMenuText;
+
+fn setup_controls_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    position_type: PositionType::Absolute,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            },
+            ControlsMenuRoot,
+            Name::new("ControlsMenu"),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("monogram.ttf"),
+                        font_size: 30.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                ControlsMenuText,
+            ));
+        });
+}
+
+fn key_names(keys: &[KeyCode]) -> String {
+    if keys.is_empty() {
+        return "-".to_string();
+    }
+    keys.iter().map(|key| format!("{key:?}")).collect::<Vec<_>>().join(", ")
+}
+
+fn button_names(buttons: &[GamepadButtonType]) -> String {
+    buttons.iter().map(|button| format!("{button:?}")).collect::<Vec<_>>().join(", ")
+}
+
+fn bind_status(input: String, action: Action, conflicts: Option<Vec<Action>>) -> String {
+    let Some(conflicts) = conflicts else {
+        return format!("{input} is the last input of {}", Action::Pause.name());
+    };
+    if conflicts.is_empty() {
+        format!("{input} added to {}", action.name())
+    } else {
+        let names = conflicts.iter().map(Action::name).collect::<Vec<_>>().join(", ");
+        format!("{input} moved from {names} to {}", action.name())
+    }
+}
+
+fn controls_menu_system(
+    mut menu: ResMut<ControlsMenu>,
+    mut input_map: ResMut<InputMap>,
+    mut actions: ResMut<ActionState>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+) {
+    let button_just_pressed = |types: &[GamepadButtonType]| {
+        gamepads
+            .iter()
+            .any(|g| gamepad_buttons.any_just_pressed(types.iter().map(|t| GamepadButton::new(g, *t))))
+    };
+
+    if menu.waiting {
+        let action = Action::ALL[menu.selected];
+        if keyboard_input.just_pressed(KeyCode::Escape) || button_just_pressed(&[GamepadButtonType::Select]) {
+            menu.waiting = false;
+            menu.status = "Cancelled".to_string();
+        } else if let Some(key) = keyboard_input.get_just_pressed().next().copied() {
+            menu.waiting = false;
+            let conflicts = input_map.bind(action, key);
+            menu.status = bind_status(format!("{key:?}"), action, conflicts);
+            save_user_config(INPUT_MAP_FILE, &*input_map);
+        } else if let Some(button) = gamepad_buttons.get_just_pressed().next().copied() {
+            menu.waiting = false;
+            let conflicts = input_map.bind_button(action, button.button_type);
+            menu.status = bind_status(format!("{:?}", button.button_type), action, conflicts);
+            save_user_config(INPUT_MAP_FILE, &*input_map);
+        }
+        return;
+    }
+
+    // Opened through the Pause action, which is disabled while the menu is open
+    let open = !menu.open && actions.just_pressed(Action::Pause);
+    let close = menu.open
+        && (keyboard_input.just_pressed(KeyCode::Escape)
+            || input_map.just_pressed(Action::Pause, &keyboard_input)
+            || button_just_pressed(input_map.buttons(Action::Pause))
+            || button_just_pressed(&[GamepadButtonType::East]));
+    if open || close {
+        menu.open = !menu.open;
+        menu.status.clear();
+        actions.enabled = !menu.open;
+        rapier_config.physics_pipeline_active = !menu.open;
+        return;
+    }
+    if !menu.open {
+        return;
+    }
+
+    let count = Action::ALL.len();
+    if keyboard_input.just_pressed(KeyCode::Up) || button_just_pressed(&[GamepadButtonType::DPadUp]) {
+        menu.selected = (menu.selected + count - 1) % count;
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) || button_just_pressed(&[GamepadButtonType::DPadDown]) {
+        menu.selected = (menu.selected + 1) % count;
+    }
+    if keyboard_input.just_pressed(KeyCode::Return) || button_just_pressed(&[GamepadButtonType::South]) {
+        menu.waiting = true;
+        menu.status.clear();
+    }
+    if keyboard_input.any_just_pressed([KeyCode::Back, KeyCode::Delete])
+        || button_just_pressed(&[GamepadButtonType::West])
+    {
+        let action = Action::ALL[menu.selected];
+        if action == Action::Pause {
+            // Without a pause key the menu could not be opened again
+            menu.status = "Pause needs a key".to_string();
+        } else {
+            input_map.clear(action);
+            menu.status = format!("Cleared {}", action.name());
+            save_user_config(INPUT_MAP_FILE, &*input_map);
+        }
+    }
+}
+
+fn controls_menu_text_system(
+    menu: Res<ControlsMenu>,
+    input_map: Res<InputMap>,
+    mut root_query: Query<&mut Visibility, With<ControlsMenuRoot>>,
+    mut text_query: Query<&mut Text, With<ControlsMenuText>>,
+) {
+    if !menu.is_changed() && !input_map.is_changed() {
+        return;
+    }
+    for mut visibility in root_query.iter_mut() {
+        visibility.is_visible = menu.open;
+    }
+
+    let mut text = "Controls\n\n".to_string();
+    for (i, action) in Action::ALL.iter().enumerate() {
+        let cursor = if i == menu.selected { "> " } else { "  " };
+        let keys = if i == menu.selected && menu.waiting {
+            "press a key...".to_string()
+        } else {
+            let buttons = input_map.buttons(*action);
+            if buttons.is_empty() {
+                key_names(input_map.keys(*action))
+            } else {
+                format!("{} / {}", key_names(input_map.keys(*action)), button_names(buttons))
+            }
+        };
+        // Only possible when the file was edited by hand
+        let conflict = input_map.keys(*action).iter().any(|key| !input_map.conflicts(*action, *key).is_empty())
+            || input_map
+                .buttons(*action)
+                .iter()
+                .any(|button| !input_map.button_conflicts(*action, *button).is_empty());
+        let warning = if conflict { " (conflict)" } else { "" };
+        text += &format!("{cursor}{}: {keys}{warning}\n", action.name());
+    }
+    text += "\nEnter/A: add key or button  Backspace/X: clear  Esc/B: close\n";
+    text += &menu.status;
+
+    for mut menu_text in text_query.iter_mut() {
+        if let Some(section) = menu_text.sections.first_mut() {
+            section.value = text.clone();
+        }
+    }
+}
+
+pub struct ControlsMenuPlugin;
+
+impl Plugin for ControlsMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ControlsMenu>()
+            .add_startup_system(setup_controls_menu)
+            .add_system(controls_menu_system)
+            .add_system(controls_menu_text_system.after(controls_menu_system));
+    }
+}
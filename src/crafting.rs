@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    climbing::Climber,
+    config::load_config,
+    input::{Action, ActionState, InputMap},
+    inventory::{Inventory, InventoryEvent, Items},
+    tools::{Toolbelt, Tools},
+    Player,
+};
+
+#[derive(Deserialize, Clone)]
+pub enum RecipeOutput {
+    // Id of a tool in tools.ron
+    Tool(String),
+    // Id of an item in items.ron, put in the inventory
+    Item(String),
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Recipe {
+    pub name: String,
+    // Item ids and counts taken from the inventory
+    pub inputs: BTreeMap<String, u32>,
+    pub output: RecipeOutput,
+    #[serde(default = "default_count")]
+    pub count: u32,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Consumable {
+    // Climbing stamina restored, 1.0 is a full bar
+    pub stamina: f32,
+}
+
+#[derive(Resource, Deserialize, Default)]
+#[serde(default)]
+pub struct Recipes {
+    pub recipes: Vec<Recipe>,
+    // Effects of items used with the Interact action, by item id
+    pub consumables: BTreeMap<String, Consumable>,
+}
+
+impl Recipe {
+    // Inputs the player is short of, empty when it can be crafted
+    fn missing(&self, inventory: &Inventory) -> Vec<(String, u32)> {
+        self.inputs
+            .iter()
+            .filter(|(id, count)| inventory.count(id) < **count)
+            .map(|(id, count)| (id.clone(), *count - inventory.count(id)))
+            .collect()
+    }
+}
+
+#[derive(Resource, Default)]
+struct CraftingMenu {
+    open: bool,
+    selected: usize,
+    status: String,
+}
+
+#[derive(Component)]
+struct CraftingMenuRoot;
+
+#[derive(Component)]
+struct CraftingMenuText;
+
+fn setup_crafting_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    position_type: PositionType::Absolute,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            },
+            CraftingMenuRoot,
+            Name::new("CraftingMenu"),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("monogram.ttf"),
+                        font_size: 30.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                CraftingMenuText,
+            ));
+        });
+}
+
+// Recipes whose output is not in tools.ron or items.ron would take the inputs and give nothing
+fn validate_recipes_system(mut recipes: ResMut<Recipes>, tools: Res<Tools>, items: Res<Items>) {
+    recipes.recipes.retain(|recipe| {
+        let known = match &recipe.output {
+            RecipeOutput::Tool(id) => tools.get(id).is_some(),
+            RecipeOutput::Item(id) => items.get(id).is_some(),
+        };
+        if !known {
+            warn!("Recipe {} makes something that does not exist, it is left out", recipe.name);
+        }
+        known
+    });
+}
+
+fn inputs_text<'a>(inputs: impl Iterator<Item = (&'a String, u32)>, items: &Items) -> String {
+    inputs.map(|(id, count)| format!("{count} {}", items.name(id))).collect::<Vec<_>>().join(", ")
+}
+
+fn output_name(recipe: &Recipe, tools: &Tools, items: &Items) -> String {
+    let name = match &recipe.output {
+        RecipeOutput::Tool(id) => tools.get(id).map_or(id.clone(), |tool| tool.name.clone()),
+        RecipeOutput::Item(id) => items.name(id).to_string(),
+    };
+    if recipe.count > 1 {
+        format!("{} x{name}", recipe.count)
+    } else {
+        name
+    }
+}
+
+// Whether the inventory has room for the output, tools go on the toolbelt and always fit
+fn output_fits(recipe: &Recipe, inventory: &Inventory, items: &Items) -> bool {
+    match &recipe.output {
+        RecipeOutput::Tool(_) => true,
+        RecipeOutput::Item(id) => inventory.space_for(id, items) >= recipe.count,
+    }
+}
+
+// Takes the inputs and gives the output, the inputs and space have to be checked first
+fn craft(recipe: &Recipe, inventory: &mut Inventory, toolbelt: &mut Toolbelt, tools: &Tools, items: &Items) {
+    for (id, count) in recipe.inputs.iter() {
+        inventory.remove(id, *count);
+    }
+    match &recipe.output {
+        RecipeOutput::Tool(id) => {
+            if let Some(tool) = tools.get(id) {
+                for _ in 0..recipe.count {
+                    toolbelt.add(tool);
+                }
+            }
+        }
+        RecipeOutput::Item(id) => {
+            inventory.add(id, recipe.count, items);
+        }
+    }
+}
+
+fn crafting_menu_system(
+    mut menu: ResMut<CraftingMenu>,
+    mut actions: ResMut<ActionState>,
+    input_map: Res<InputMap>,
+    mut player_query: Query<(&mut Inventory, &mut Toolbelt), With<Player>>,
+    recipes: Res<Recipes>,
+    tools: Res<Tools>,
+    items: Res<Items>,
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+) {
+    let button_just_pressed = |types: &[GamepadButtonType]| {
+        gamepads
+            .iter()
+            .any(|g| gamepad_buttons.any_just_pressed(types.iter().map(|t| GamepadButton::new(g, *t))))
+    };
+
+    // Opened through the Craft action, which is disabled while the menu is open
+    let open = !menu.open && actions.just_pressed(Action::Craft);
+    let close = menu.open
+        && (keyboard_input.just_pressed(KeyCode::Escape)
+            || input_map.just_pressed(Action::Craft, &keyboard_input)
+            || button_just_pressed(input_map.buttons(Action::Craft))
+            || button_just_pressed(&[GamepadButtonType::East]));
+    if open || close {
+        menu.open = !menu.open;
+        menu.status.clear();
+        actions.enabled = !menu.open;
+        return;
+    }
+    if !menu.open || recipes.recipes.is_empty() {
+        return;
+    }
+
+    let count = recipes.recipes.len();
+    if keyboard_input.just_pressed(KeyCode::Up) || button_just_pressed(&[GamepadButtonType::DPadUp]) {
+        menu.selected = (menu.selected + count - 1) % count;
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) || button_just_pressed(&[GamepadButtonType::DPadDown]) {
+        menu.selected = (menu.selected + 1) % count;
+    }
+    if keyboard_input.just_pressed(KeyCode::Return) || button_just_pressed(&[GamepadButtonType::South]) {
+        let recipe = &recipes.recipes[menu.selected.min(count - 1)];
+        let Ok((mut inventory, mut toolbelt)) = player_query.get_single_mut() else {
+            return;
+        };
+        let missing = recipe.missing(&inventory);
+        menu.status = if !missing.is_empty() {
+            format!("Need {} more", inputs_text(missing.iter().map(|(id, count)| (id, *count)), &items))
+        } else if !output_fits(recipe, &inventory, &items) {
+            "Inventory full".to_string()
+        } else {
+            craft(recipe, &mut inventory, &mut toolbelt, &tools, &items);
+            format!("Crafted {}", output_name(recipe, &tools, &items))
+        };
+    }
+}
+
+fn crafting_menu_text_system(
+    menu: Res<CraftingMenu>,
+    mut inventory_events: EventReader<InventoryEvent>,
+    inventory_query: Query<&Inventory, With<Player>>,
+    recipes: Res<Recipes>,
+    items: Res<Items>,
+    mut root_query: Query<&mut Visibility, With<CraftingMenuRoot>>,
+    mut text_query: Query<&mut Text, With<CraftingMenuText>>,
+) {
+    // Also rebuilt when the inputs the player holds change while the menu is open
+    let inventory_changed = inventory_events.iter().count() > 0;
+    if !menu.is_changed() && !inventory_changed {
+        return;
+    }
+    for mut visibility in root_query.iter_mut() {
+        visibility.is_visible = menu.open;
+    }
+    let Ok(inventory) = inventory_query.get_single() else {
+        return;
+    };
+
+    let mut text = "Crafting\n\n".to_string();
+    for (i, recipe) in recipes.recipes.iter().enumerate() {
+        let cursor = if i == menu.selected { "> " } else { "  " };
+        let available = if recipe.missing(inventory).is_empty() { "" } else { " (missing inputs)" };
+        let inputs = inputs_text(recipe.inputs.iter().map(|(id, count)| (id, *count)), &items);
+        text += &format!("{cursor}{}: {inputs}{available}\n", recipe.name);
+    }
+    text += "\nEnter/A: craft  Esc/B: close\n";
+    text += &menu.status;
+
+    for mut menu_text in text_query.iter_mut() {
+        if let Some(section) = menu_text.sections.first_mut() {
+            section.value = text.clone();
+        }
+    }
+}
+
+// Interact uses the first consumable in the inventory
+fn use_consumable_system(
+    mut player_query: Query<(&mut Inventory, &mut Climber), With<Player>>,
+    actions: Res<ActionState>,
+    recipes: Res<Recipes>,
+) {
+    if !actions.just_pressed(Action::Interact) {
+        return;
+    }
+    for (mut inventory, mut climber) in player_query.iter_mut() {
+        let Some((id, consumable)) = inventory
+            .totals()
+            .into_iter()
+            .find_map(|(id, _)| recipes.consumables.get(&id).map(|consumable| (id, consumable)))
+        else {
+            continue;
+        };
+        climber.stamina = (climber.stamina + consumable.stamina).min(1.0);
+        inventory.remove(&id, 1);
+    }
+}
+
+pub struct CraftingPlugin;
+
+impl Plugin for CraftingPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_config::<Recipes>("recipes.ron"))
+            .init_resource::<CraftingMenu>()
+            .add_startup_system(setup_crafting_menu)
+            .add_startup_system(validate_recipes_system)
+            .add_system(crafting_menu_system)
+            .add_system(crafting_menu_text_system.after(crafting_menu_system))
+            .add_system(use_consumable_system);
+    }
+}
@@ -0,0 +1,158 @@
+use bevy::{
+    prelude::*,
+    render::{primitives::Aabb, view::VisibilitySystems},
+    transform::TransformSystem,
+    utils::HashSet,
+};
+
+use crate::{
+    curvature::WorldCurvature,
+    shaders::{CustomMaterial, ShaderGlobals},
+    MainCamera,
+};
+
+// Replaces NoFrustumCulling for meshes that are moved by the world bending in the vertex shader.
+// The bounds of the mesh are stretched downwards by the largest bend it can receive,
+// and sideways by the furthest its material can sway.
+#[derive(Component, Default)]
+pub struct BendCulling {
+    base: Option<Aabb>,
+}
+
+fn corners(min: Vec3, max: Vec3) -> [Vec3; 8] {
+    [
+        Vec3::new(min.x, min.y, min.z),
+        Vec3::new(max.x, min.y, min.z),
+        Vec3::new(min.x, max.y, min.z),
+        Vec3::new(max.x, max.y, min.z),
+        Vec3::new(min.x, min.y, max.z),
+        Vec3::new(max.x, min.y, max.z),
+        Vec3::new(min.x, max.y, max.z),
+        Vec3::new(max.x, max.y, max.z),
+    ]
+}
+
+fn bounds(points: impl Iterator<Item = Vec3>) -> (Vec3, Vec3) {
+    points.fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)), |(min, max), p| {
+        (min.min(p), max.max(p))
+    })
+}
+
+fn reset_bend_culling_system(
+    mut mesh_events: EventReader<AssetEvent<Mesh>>,
+    mut query: Query<(&Handle<Mesh>, &mut BendCulling)>,
+) {
+    let modified: HashSet<Handle<Mesh>> = mesh_events
+        .iter()
+        .filter_map(|ev| match ev {
+            AssetEvent::Modified { handle } => Some(handle.clone_weak()),
+            _ => None,
+        })
+        .collect();
+    if modified.is_empty() {
+        return;
+    }
+
+    for (handle, mut culling) in query.iter_mut() {
+        if modified.contains(handle) {
+            culling.base = None;
+        }
+    }
+}
+
+// Closest and furthest distance from a point to a range along one axis
+fn distance_range(min: f32, max: f32, point: f32) -> (f32, f32) {
+    let far = (min - point).abs().max((max - point).abs());
+    let near = if (min..=max).contains(&point) {
+        0.0
+    } else {
+        (min - point).abs().min((max - point).abs())
+    };
+    (near, far)
+}
+
+fn bend_culling_system(
+    camera_query: Query<&GlobalTransform, With<MainCamera>>,
+    curvature: Res<WorldCurvature>,
+    globals: Res<ShaderGlobals>,
+    meshes: Res<Assets<Mesh>>,
+    custom_materials: Res<Assets<CustomMaterial>>,
+    mut query: Query<(
+        &Handle<Mesh>,
+        Option<&Handle<CustomMaterial>>,
+        &GlobalTransform,
+        &mut BendCulling,
+        &mut Aabb,
+    )>,
+) {
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+    let camera_position = camera_transform.translation();
+    // Gusts reach at most the full wind, see sway_offset in custom.wgsl
+    let max_sway = globals.wind.length() + globals.wind_push_strength;
+
+    for (handle, material, transform, mut culling, mut aabb) in query.iter_mut() {
+        if culling.base.is_none() {
+            culling.base = meshes.get(handle).and_then(|mesh| mesh.compute_aabb());
+        }
+        let Some(base) = culling.base else {
+            continue;
+        };
+
+        let matrix = transform.compute_matrix();
+        let (mut min, mut max) = bounds(
+            corners(base.min().into(), base.max().into())
+                .into_iter()
+                .map(|p| matrix.transform_point3(p)),
+        );
+
+        let sway = material
+            .and_then(|handle| custom_materials.get(handle))
+            .map_or(0.0, |material| material.sway.abs());
+        let padding = Vec3::new(1.0, 0.0, 1.0) * sway * max_sway;
+        min -= padding;
+        max += padding;
+
+        // The displacement grows with the distance along x and z separately,
+        // so its extremes are at the nearest and furthest distances
+        let (near_x, far_x) = distance_range(min.x, max.x, camera_position.x);
+        let (near_z, far_z) = distance_range(min.z, max.z, camera_position.z);
+        let offsets = [
+            curvature.offset_at(near_x, near_z),
+            curvature.offset_at(near_x, far_z),
+            curvature.offset_at(far_x, near_z),
+            curvature.offset_at(far_x, far_z),
+        ];
+        let min_offset = offsets.iter().copied().fold(f32::MAX, f32::min);
+        let max_offset = offsets.iter().copied().fold(f32::MIN, f32::max);
+        let bent_min = Vec3::new(min.x, min.y + min_offset, min.z);
+        let bent_max = Vec3::new(max.x, max.y + max_offset, max.z);
+
+        let inverse = matrix.inverse();
+        let (local_min, local_max) = bounds(
+            corners(bent_min, bent_max)
+                .into_iter()
+                .map(|p| inverse.transform_point3(p)),
+        );
+        *aabb = Aabb::from_min_max(local_min, local_max);
+    }
+}
+
+pub struct CullingPlugin;
+
+impl Plugin for CullingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            reset_bend_culling_system.before(bend_culling_system),
+        )
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            bend_culling_system
+                .after(TransformSystem::TransformPropagate)
+                .after(VisibilitySystems::CalculateBounds)
+                .before(VisibilitySystems::CheckVisibility),
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::load_config, constants::DEFAULT_BENDING, shaders::ShaderGlobals};
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Reflect, Serialize, Deserialize)]
+pub enum CurvatureMode {
+    None,
+    // Bends down along the camera's z axis only, like the world is on a rolling log
+    #[default]
+    RollingLog,
+    // Bends down with the horizontal distance to the camera, like a small planet
+    Spherical,
+}
+
+// How the vertex shader bends the world away from the camera.
+// The displacement only depends on x and z, so bent positions can always be mapped back.
+#[derive(Resource, Reflect, Serialize, Deserialize, Clone)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct WorldCurvature {
+    pub mode: CurvatureMode,
+    pub rolling_log_strength: f32,
+    pub spherical_strength: f32,
+    // Seconds it takes to ease into a newly selected mode
+    pub transition_time: f32,
+    // Strengths in use right now, eased towards the ones of the selected mode
+    #[serde(skip)]
+    rolling_log: f32,
+    #[serde(skip)]
+    spherical: f32,
+}
+
+impl Default for WorldCurvature {
+    fn default() -> Self {
+        let mut curvature = Self {
+            mode: CurvatureMode::RollingLog,
+            rolling_log_strength: DEFAULT_BENDING,
+            spherical_strength: 0.01,
+            transition_time: 0.5,
+            rolling_log: 0.0,
+            spherical: 0.0,
+        };
+        curvature.snap();
+        curvature
+    }
+}
+
+impl WorldCurvature {
+    // Rolling log and spherical strengths of the selected mode
+    fn target(&self) -> (f32, f32) {
+        match self.mode {
+            CurvatureMode::None => (0.0, 0.0),
+            CurvatureMode::RollingLog => (self.rolling_log_strength, 0.0),
+            CurvatureMode::Spherical => (0.0, self.spherical_strength),
+        }
+    }
+
+    // Skips the easing
+    pub fn snap(&mut self) {
+        (self.rolling_log, self.spherical) = self.target();
+    }
+
+    // Vertical displacement at the given horizontal distance from the camera
+    pub fn offset_at(&self, dx: f32, dz: f32) -> f32 {
+        -(self.rolling_log * dz * dz + self.spherical * (dx * dx + dz * dz))
+    }
+
+    pub fn bend_offset(&self, position: Vec3, camera_position: Vec3) -> f32 {
+        let d = position - camera_position;
+        self.offset_at(d.x, d.z)
+    }
+
+    // Where the shader draws a world position
+    pub fn bend(&self, position: Vec3, camera_position: Vec3) -> Vec3 {
+        position + Vec3::Y * self.bend_offset(position, camera_position)
+    }
+
+    // World position of something drawn at the given position, e.g. under the cursor
+    pub fn unbend(&self, bent: Vec3, camera_position: Vec3) -> Vec3 {
+        bent - Vec3::Y * self.bend_offset(bent, camera_position)
+    }
+}
+
+fn curvature_system(
+    mut curvature: ResMut<WorldCurvature>,
+    mut globals: ResMut<ShaderGlobals>,
+    time: Res<Time>,
+) {
+    let (rolling_log, spherical) = curvature.target();
+    if curvature.rolling_log != rolling_log || curvature.spherical != spherical {
+        if curvature.transition_time <= 0.0 {
+            curvature.snap();
+        } else {
+            let t = (time.delta_seconds() / curvature.transition_time).min(1.0);
+            curvature.rolling_log += (rolling_log - curvature.rolling_log) * t;
+            curvature.spherical += (spherical - curvature.spherical) * t;
+            if (curvature.rolling_log - rolling_log).abs() < 0.0001
+                && (curvature.spherical - spherical).abs() < 0.0001
+            {
+                curvature.snap();
+            }
+        }
+    }
+
+    globals.rolling_log_bending = curvature.rolling_log;
+    globals.spherical_bending = curvature.spherical;
+}
+
+pub struct CurvaturePlugin;
+
+impl Plugin for CurvaturePlugin {
+    fn build(&self, app: &mut App) {
+        let mut curvature = load_config::<WorldCurvature>("world_curvature.ron");
+        curvature.snap();
+
+        app.insert_resource(curvature)
+            .register_type::<WorldCurvature>() // Only needed for in-game inspector
+            .add_system(curvature_system);
+    }
+}
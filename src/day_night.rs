@@ -0,0 +1,87 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::load_config;
+
+// Time of day and the values that change between day and night.
+// Day values come from the Atmosphere, night values are set here.
+#[derive(Resource, Reflect, Serialize, Deserialize, Clone)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct DayNight {
+    // Seconds of a full day, 0 stops the clock
+    pub day_length: f32,
+    // 0.0 is midnight, 0.5 is noon
+    pub time_of_day: f32,
+    pub day_clear_color: Color,
+    pub night_clear_color: Color,
+    pub night_ambient: Color,
+    // Fog distance at night relative to the day
+    pub night_fog_distance: f32,
+    // Sun brightness at night relative to the day, i.e. moonlight
+    pub night_sunlight: f32,
+    pub day_glow: f32,
+    pub night_glow: f32,
+}
+
+impl Default for DayNight {
+    fn default() -> Self {
+        Self {
+            day_length: 240.0,
+            time_of_day: 0.3,
+            day_clear_color: Color::rgb(27.0 / 255.0, 28.0 / 255.0, 17.0 / 255.0),
+            night_clear_color: Color::rgb(4.0 / 255.0, 5.0 / 255.0, 10.0 / 255.0),
+            night_ambient: Color::rgb(0.002, 0.002, 0.006),
+            night_fog_distance: 0.6,
+            night_sunlight: 0.15,
+            day_glow: 1.0,
+            night_glow: 2.5,
+        }
+    }
+}
+
+impl DayNight {
+    // 0.0 at night and 1.0 during the day, with a short dusk and dawn in between
+    pub fn daylight(&self) -> f32 {
+        let sun_height = -(self.time_of_day * TAU).cos();
+        let t = ((sun_height + 0.2) / 0.4).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    // Lerps from the night value to the day value
+    pub fn blend(&self, night: f32, day: f32) -> f32 {
+        night + (day - night) * self.daylight()
+    }
+
+    pub fn blend_color(&self, night: Color, day: Color) -> Color {
+        let t = self.daylight();
+        Color::rgb(
+            night.r() + (day.r() - night.r()) * t,
+            night.g() + (day.g() - night.g()) * t,
+            night.b() + (day.b() - night.b()) * t,
+        )
+    }
+}
+
+fn day_night_system(
+    mut day_night: ResMut<DayNight>,
+    mut clear_color: ResMut<ClearColor>,
+    time: Res<Time>,
+) {
+    if day_night.day_length > 0.0 {
+        day_night.time_of_day = (day_night.time_of_day + time.delta_seconds() / day_night.day_length).fract();
+    }
+    clear_color.0 = day_night.blend_color(day_night.night_clear_color, day_night.day_clear_color);
+}
+
+pub struct DayNightPlugin;
+
+impl Plugin for DayNightPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_config::<DayNight>("day_night.ron"))
+            .register_type::<DayNight>() // Only needed for in-game inspector
+            .add_system(day_night_system);
+    }
+}
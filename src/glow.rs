@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block_atlas::BlockAtlas,
+    config::load_config,
+    shaders::{ShaderGlobals, MAX_GLOW_LIGHTS},
+    BlockMap, BlockPosition, Player, Root,
+};
+
+// Exposed emissive blocks near the player light up their surroundings
+#[derive(Resource, Reflect, Serialize, Deserialize, Clone)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct GlowSettings {
+    pub light_radius: f32,
+    pub light_intensity: f32,
+    // Blocks further than this from the player in any axis are not considered
+    pub search_distance: i64,
+    // Radians per second of pulsing emissive surfaces
+    pub pulse_speed: f32,
+}
+
+impl Default for GlowSettings {
+    fn default() -> Self {
+        Self {
+            light_radius: 4.0,
+            light_intensity: 0.3,
+            search_distance: 10,
+            pulse_speed: 2.0,
+        }
+    }
+}
+
+// Same as glow_pulse in custom.wgsl
+fn glow_pulse(amount: f32, phase: f32, time: f32, speed: f32) -> f32 {
+    1.0 - amount * (0.5 + 0.5 * (time * speed + phase).sin())
+}
+
+// Blocks that were spawned or fell this frame. Moving a block removes its old position.
+fn track_emissive_blocks_system(
+    query: Query<(&BlockPosition, &Root), Changed<BlockPosition>>,
+    atlas: Res<BlockAtlas>,
+    mut blockmap: ResMut<BlockMap>,
+) {
+    for (position, root) in query.iter() {
+        if atlas.emissive(root.resource.name()).truncate() != Vec3::ZERO {
+            blockmap.emissive.insert(position.0);
+        }
+    }
+}
+
+fn glow_light_system(
+    settings: Res<GlowSettings>,
+    blockmap: Res<BlockMap>,
+    atlas: Res<BlockAtlas>,
+    root_query: Query<&Root>,
+    player_query: Query<&Transform, With<Player>>,
+    mut globals: ResMut<ShaderGlobals>,
+    time: Res<Time>,
+) {
+    globals.glow_pulse_speed = settings.pulse_speed;
+
+    let Ok(player_t) = player_query.get_single() else {
+        return;
+    };
+    let center = player_t.translation.round();
+
+    let mut lights = Vec::new();
+    for position in blockmap.emissive.iter() {
+        let world = Vec3::from(*position);
+        if (world - center).abs().max_element() > settings.search_distance as f32 {
+            continue;
+        }
+        let Some(root) = blockmap.entities.get(position).and_then(|e| root_query.get(*e).ok()) else {
+            continue;
+        };
+
+        // Blocks buried on all sides give no light
+        let exposed = [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)]
+            .into_iter()
+            .any(|offset| !blockmap.entities.contains_key(&(*position + offset.into())));
+        if !exposed {
+            continue;
+        }
+
+        let emissive = atlas.emissive(root.resource.name());
+        lights.push((world.distance_squared(player_t.translation), world, emissive));
+    }
+    lights.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let t = time.elapsed_seconds_wrapped();
+    globals.glow_light_count = lights.len().min(MAX_GLOW_LIGHTS) as u32;
+    for (i, (_, world, emissive)) in lights.into_iter().take(MAX_GLOW_LIGHTS).enumerate() {
+        let phase = world.dot(Vec3::new(0.7, 1.3, 0.9));
+        let pulse = glow_pulse(emissive.w, phase, t, settings.pulse_speed);
+        globals.glow_lights[i] = world.extend(settings.light_radius);
+        globals.glow_light_colors[i] = (emissive.truncate() * pulse * settings.light_intensity).extend(1.0);
+    }
+}
+
+pub struct GlowPlugin;
+
+impl Plugin for GlowPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_config::<GlowSettings>("glow.ron"))
+            .register_type::<GlowSettings>() // Only needed for in-game inspector
+            .add_system(track_emissive_blocks_system)
+            .add_system(glow_light_system.after(track_emissive_blocks_system));
+    }
+}
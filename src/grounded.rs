@@ -0,0 +1,161 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::load_config,
+    input::{Action, ActionState},
+    MainCamera,
+};
+
+// Contacts whose normal points further from straight up than this count as walls
+const MIN_GROUND_NORMAL_Y: f32 = 0.5;
+
+#[derive(Resource, Reflect, Serialize, Deserialize, Clone)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct JumpSettings {
+    // Vertical speed set when jumping
+    pub jump_speed: f32,
+    // Seconds after walking off an edge during which a jump still works
+    pub coyote_time: f32,
+    // Seconds a jump pressed in the air is remembered until landing
+    pub jump_buffer: f32,
+    // Upward speed is multiplied by this when the jump is released early
+    pub jump_cut: f32,
+    // Radius of the ball cast down from the centre, a bit smaller than the collider so walls are not hit
+    pub probe_radius: f32,
+    // How far the ball is cast, reaching a bit below the collider
+    pub probe_distance: f32,
+    // Landings faster than this shake the camera
+    pub hard_landing_speed: f32,
+    pub landing_shake: f32,
+}
+
+impl Default for JumpSettings {
+    fn default() -> Self {
+        Self {
+            jump_speed: 7.5,
+            coyote_time: 0.12,
+            jump_buffer: 0.15,
+            jump_cut: 0.5,
+            probe_radius: 0.45,
+            probe_distance: 0.1,
+            hard_landing_speed: 7.0,
+            landing_shake: 0.05,
+        }
+    }
+}
+
+// Tracks standing on something for entities that can jump
+#[derive(Component, Default)]
+pub struct Grounded {
+    pub on_ground: bool,
+    // Seconds since the entity was last on the ground
+    pub air_time: f32,
+    // Seconds left of a buffered jump, zero when none is waiting
+    jump_buffer_left: f32,
+    // Set while rising from a jump that started with the button held, so releasing it can cut the
+    // jump short. Buffered taps released before landing give a full jump instead of a cut one.
+    jumping: bool,
+    // Downward speed of the previous frame, reported when landing
+    fall_speed: f32,
+}
+
+pub struct LandingEvent {
+    pub entity: Entity,
+    // Downward speed right before touching the ground
+    pub speed: f32,
+}
+
+fn grounded_system(
+    mut query: Query<(Entity, &Transform, &Velocity, &mut Grounded)>,
+    mut landing_events: EventWriter<LandingEvent>,
+    rapier_context: Res<RapierContext>,
+    settings: Res<JumpSettings>,
+    time: Res<Time>,
+) {
+    let probe = Collider::ball(settings.probe_radius);
+    for (entity, transform, velocity, mut grounded) in query.iter_mut() {
+        // A ball cast instead of a single ray, so edges and seams between blocks still count
+        let hit = rapier_context.cast_shape(
+            transform.translation,
+            Quat::IDENTITY,
+            Vec3::NEG_Y,
+            &probe,
+            settings.probe_distance,
+            QueryFilter::new().exclude_rigid_body(entity).exclude_sensors(),
+        );
+        let on_ground = hit.map_or(false, |(_, toi)| -toi.normal1.y > MIN_GROUND_NORMAL_Y)
+            // Not while moving up through the probe distance right after a jump
+            && velocity.linvel.y <= settings.jump_speed * 0.5;
+
+        if on_ground && !grounded.on_ground {
+            landing_events.send(LandingEvent { entity, speed: grounded.fall_speed });
+        }
+        grounded.on_ground = on_ground;
+        grounded.air_time = if on_ground { 0.0 } else { grounded.air_time + time.delta_seconds() };
+        grounded.fall_speed = (-velocity.linvel.y).max(0.0);
+    }
+}
+
+fn jump_system(
+    mut query: Query<(&mut Velocity, &mut Grounded)>,
+    actions: Res<ActionState>,
+    settings: Res<JumpSettings>,
+    time: Res<Time>,
+) {
+    for (mut velocity, mut grounded) in query.iter_mut() {
+        if actions.just_pressed(Action::Jump) {
+            grounded.jump_buffer_left = settings.jump_buffer;
+        }
+
+        let can_jump = grounded.on_ground || grounded.air_time < settings.coyote_time;
+        if grounded.jump_buffer_left > 0.0 && can_jump {
+            velocity.linvel.y = settings.jump_speed;
+            grounded.jump_buffer_left = 0.0;
+            grounded.jumping = actions.pressed(Action::Jump);
+            // Uses up the coyote time so it cannot give a second jump
+            grounded.on_ground = false;
+            grounded.air_time = settings.coyote_time;
+        }
+        grounded.jump_buffer_left = (grounded.jump_buffer_left - time.delta_seconds()).max(0.0);
+
+        // Variable jump height, letting go early cuts the rise short
+        if grounded.jumping && velocity.linvel.y > 0.0 && !actions.pressed(Action::Jump) {
+            velocity.linvel.y *= settings.jump_cut;
+            grounded.jumping = false;
+        }
+        if velocity.linvel.y <= 0.0 {
+            grounded.jumping = false;
+        }
+    }
+}
+
+fn landing_shake_system(
+    mut landing_events: EventReader<LandingEvent>,
+    mut camera_query: Query<&mut MainCamera>,
+    settings: Res<JumpSettings>,
+) {
+    for ev in landing_events.iter() {
+        if ev.speed < settings.hard_landing_speed {
+            continue;
+        }
+        for mut camera in camera_query.iter_mut() {
+            camera.shake_intensity += settings.landing_shake * (ev.speed - settings.hard_landing_speed + 1.0);
+        }
+    }
+}
+
+pub struct GroundedPlugin;
+
+impl Plugin for GroundedPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_config::<JumpSettings>("jump.ron"))
+            .register_type::<JumpSettings>() // Only needed for in-game inspector
+            .add_event::<LandingEvent>()
+            .add_system(grounded_system)
+            .add_system(jump_system.after(grounded_system))
+            .add_system(landing_shake_system.after(grounded_system));
+    }
+}
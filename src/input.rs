@@ -0,0 +1,402 @@
+use std::collections::BTreeMap;
+
+use bevy::{input::InputSystem, prelude::*, utils::HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{load_config, load_user_config};
+
+pub const INPUT_MAP_FILE: &str = "input_map.ron";
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Attack,
+    ZoomIn,
+    ZoomOut,
+    Interact,
+    NextTool,
+    Craft,
+    Build,
+    Pause,
+}
+
+impl Action {
+    pub const ALL: [Action; 13] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::Attack,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::Interact,
+        Action::NextTool,
+        Action::Craft,
+        Action::Build,
+        Action::Pause,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::MoveUp => "Move up",
+            Action::MoveDown => "Move down",
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::Jump => "Jump",
+            Action::Attack => "Attack",
+            Action::ZoomIn => "Zoom in",
+            Action::ZoomOut => "Zoom out",
+            Action::Interact => "Interact",
+            Action::NextTool => "Next tool",
+            Action::Craft => "Craft",
+            Action::Build => "Build",
+            Action::Pause => "Pause",
+        }
+    }
+
+    // Direction on the ground for the move actions
+    fn movement(&self) -> Vec3 {
+        match self {
+            Action::MoveUp => Vec3::new(0.0, 0.0, -1.0),
+            Action::MoveDown => Vec3::new(0.0, 0.0, 1.0),
+            Action::MoveLeft => Vec3::new(-1.0, 0.0, 0.0),
+            Action::MoveRight => Vec3::new(1.0, 0.0, 0.0),
+            _ => Vec3::ZERO,
+        }
+    }
+}
+
+// Keys and gamepad buttons bound to each action. The defaults come from assets/input_map.ron,
+// changes made in game are saved to the user's input_map.ron and loaded on top of them.
+// An input may only be bound to one action, see conflicts.
+#[derive(Resource, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct InputMap {
+    pub bindings: BTreeMap<Action, Vec<KeyCode>>,
+    pub gamepad_bindings: BTreeMap<Action, Vec<GamepadButtonType>>,
+    // Left stick deflection below this is ignored, above it movement scales up from zero
+    pub stick_dead_zone: f32,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        Self {
+            bindings: [
+                (Action::MoveUp, vec![KeyCode::W, KeyCode::Up]),
+                (Action::MoveDown, vec![KeyCode::S, KeyCode::Down]),
+                (Action::MoveLeft, vec![KeyCode::A, KeyCode::Left]),
+                (Action::MoveRight, vec![KeyCode::D, KeyCode::Right]),
+                (Action::Jump, vec![KeyCode::Space]),
+                (Action::Attack, vec![KeyCode::C]),
+                (Action::ZoomIn, vec![KeyCode::Z]),
+                (Action::ZoomOut, vec![KeyCode::X]),
+                (Action::Interact, vec![KeyCode::E]),
+                (Action::NextTool, vec![KeyCode::Q]),
+                (Action::Craft, vec![KeyCode::Tab]),
+                (Action::Build, vec![KeyCode::B]),
+                (Action::Pause, vec![KeyCode::Escape]),
+            ]
+            .into_iter()
+            .collect(),
+            gamepad_bindings: [
+                (Action::MoveUp, vec![GamepadButtonType::DPadUp]),
+                (Action::MoveDown, vec![GamepadButtonType::DPadDown]),
+                (Action::MoveLeft, vec![GamepadButtonType::DPadLeft]),
+                (Action::MoveRight, vec![GamepadButtonType::DPadRight]),
+                (Action::Jump, vec![GamepadButtonType::South]),
+                (Action::Attack, vec![GamepadButtonType::West, GamepadButtonType::RightTrigger2]),
+                (Action::ZoomIn, vec![GamepadButtonType::RightTrigger]),
+                (Action::ZoomOut, vec![GamepadButtonType::LeftTrigger]),
+                (Action::Interact, vec![GamepadButtonType::North]),
+                (Action::NextTool, vec![GamepadButtonType::RightThumb]),
+                (Action::Craft, vec![GamepadButtonType::Select]),
+                (Action::Build, vec![GamepadButtonType::LeftThumb]),
+                (Action::Pause, vec![GamepadButtonType::Start]),
+            ]
+            .into_iter()
+            .collect(),
+            stick_dead_zone: 0.2,
+        }
+    }
+}
+
+fn bound_inputs<T>(bindings: &BTreeMap<Action, Vec<T>>, action: Action) -> &[T] {
+    bindings.get(&action).map_or(&[], Vec::as_slice)
+}
+
+fn conflicts_in<T: PartialEq>(bindings: &BTreeMap<Action, Vec<T>>, action: Action, input: &T) -> Vec<Action> {
+    bindings
+        .iter()
+        .filter(|(other, inputs)| **other != action && inputs.contains(input))
+        .map(|(other, _)| *other)
+        .collect()
+}
+
+// None when the input is the last one bound to Pause, without it the pause menu could not be opened again
+fn bind_in<T: PartialEq>(
+    bindings: &mut BTreeMap<Action, Vec<T>>,
+    action: Action,
+    input: T,
+) -> Option<Vec<Action>> {
+    let conflicts = conflicts_in(bindings, action, &input);
+    if conflicts.contains(&Action::Pause) && bound_inputs(bindings, Action::Pause).len() == 1 {
+        return None;
+    }
+    for other in conflicts.iter() {
+        if let Some(inputs) = bindings.get_mut(other) {
+            inputs.retain(|i| *i != input);
+        }
+    }
+    let inputs = bindings.entry(action).or_default();
+    if !inputs.contains(&input) {
+        inputs.push(input);
+    }
+    Some(conflicts)
+}
+
+impl InputMap {
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        bound_inputs(&self.bindings, action)
+    }
+
+    pub fn buttons(&self, action: Action) -> &[GamepadButtonType] {
+        bound_inputs(&self.gamepad_bindings, action)
+    }
+
+    // Other actions the key is bound to
+    pub fn conflicts(&self, action: Action, key: KeyCode) -> Vec<Action> {
+        conflicts_in(&self.bindings, action, &key)
+    }
+
+    pub fn button_conflicts(&self, action: Action, button: GamepadButtonType) -> Vec<Action> {
+        conflicts_in(&self.gamepad_bindings, action, &button)
+    }
+
+    // Adds the key to the action and removes it from the actions it conflicts with, which are returned.
+    // None when the key is the last one of Pause, then nothing changes.
+    pub fn bind(&mut self, action: Action, key: KeyCode) -> Option<Vec<Action>> {
+        bind_in(&mut self.bindings, action, key)
+    }
+
+    pub fn bind_button(&mut self, action: Action, button: GamepadButtonType) -> Option<Vec<Action>> {
+        bind_in(&mut self.gamepad_bindings, action, button)
+    }
+
+    // Removes both the keys and the gamepad buttons. The empty entries are kept in the file,
+    // otherwise the defaults would be restored on the next start.
+    pub fn clear(&mut self, action: Action) {
+        self.bindings.insert(action, Vec::new());
+        self.gamepad_bindings.insert(action, Vec::new());
+    }
+
+    // Bindings of the actions in the user's file replace the defaults
+    fn apply_user_bindings(&mut self, user: InputMap) {
+        self.bindings.extend(user.bindings);
+        self.gamepad_bindings.extend(user.gamepad_bindings);
+        self.stick_dead_zone = user.stick_dead_zone;
+    }
+
+    // Actions added after the file was saved get their default bindings
+    fn add_missing_defaults(&mut self) {
+        let defaults = InputMap::default();
+        for (action, keys) in defaults.bindings {
+            self.bindings.entry(action).or_insert(keys);
+        }
+        for (action, buttons) in defaults.gamepad_bindings {
+            self.gamepad_bindings.entry(action).or_insert(buttons);
+        }
+    }
+
+    pub fn pressed(&self, action: Action, keyboard_input: &Input<KeyCode>) -> bool {
+        keyboard_input.any_pressed(self.keys(action).iter().copied())
+    }
+
+    pub fn just_pressed(&self, action: Action, keyboard_input: &Input<KeyCode>) -> bool {
+        keyboard_input.any_just_pressed(self.keys(action).iter().copied())
+    }
+
+    pub fn button_pressed(&self, action: Action, gamepad: Gamepad, buttons: &Input<GamepadButton>) -> bool {
+        buttons.any_pressed(self.buttons(action).iter().map(|b| GamepadButton::new(gamepad, *b)))
+    }
+
+    pub fn button_just_pressed(&self, action: Action, gamepad: Gamepad, buttons: &Input<GamepadButton>) -> bool {
+        buttons.any_just_pressed(self.buttons(action).iter().map(|b| GamepadButton::new(gamepad, *b)))
+    }
+
+    // Stick position with the dead zone removed, the length is between 0.0 and 1.0
+    fn apply_dead_zone(&self, stick: Vec2) -> Vec2 {
+        let length = stick.length();
+        if length <= self.stick_dead_zone {
+            return Vec2::ZERO;
+        }
+        let scaled = ((length - self.stick_dead_zone) / (1.0 - self.stick_dead_zone).max(0.001)).min(1.0);
+        stick / length * scaled
+    }
+}
+
+// Actions active this frame, gameplay systems read these instead of the keyboard
+#[derive(Resource)]
+pub struct ActionState {
+    // Cleared while a menu uses the keyboard
+    pub enabled: bool,
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    // Analog movement from a gamepad stick, zero when it is not used
+    stick: Vec3,
+}
+
+impl Default for ActionState {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            pressed: HashSet::default(),
+            just_pressed: HashSet::default(),
+            stick: Vec3::ZERO,
+        }
+    }
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    // Movement on the ground with a length up to 1.0. Move actions give full speed,
+    // a partly deflected stick gives less.
+    pub fn movement(&self) -> Vec3 {
+        let digital = self.pressed.iter().map(Action::movement).sum::<Vec3>().normalize_or_zero();
+        if digital != Vec3::ZERO {
+            digital
+        } else {
+            self.stick
+        }
+    }
+}
+
+// All connected gamepads control the player, so controllers can be swapped at any time
+fn action_state_system(
+    input_map: Res<InputMap>,
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    mut actions: ResMut<ActionState>,
+) {
+    actions.pressed.clear();
+    actions.just_pressed.clear();
+    actions.stick = Vec3::ZERO;
+    if !actions.enabled {
+        return;
+    }
+
+    for action in Action::ALL {
+        let pressed = input_map.pressed(action, &keyboard_input)
+            || gamepads.iter().any(|g| input_map.button_pressed(action, g, &gamepad_buttons));
+        let just_pressed = input_map.just_pressed(action, &keyboard_input)
+            || gamepads.iter().any(|g| input_map.button_just_pressed(action, g, &gamepad_buttons));
+        if pressed {
+            actions.pressed.insert(action);
+        }
+        if just_pressed {
+            actions.just_pressed.insert(action);
+        }
+    }
+
+    // The most deflected stick wins
+    for gamepad in gamepads.iter() {
+        let x = gamepad_axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX)).unwrap_or(0.0);
+        let y = gamepad_axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY)).unwrap_or(0.0);
+        let stick = input_map.apply_dead_zone(Vec2::new(x, y));
+        // Stick up moves away from the camera
+        let movement = Vec3::new(stick.x, 0.0, -stick.y);
+        if movement.length() > actions.stick.length() {
+            actions.stick = movement;
+        }
+    }
+}
+
+fn gamepad_connection_system(mut gamepad_events: EventReader<GamepadEvent>) {
+    for event in gamepad_events.iter() {
+        match &event.event_type {
+            GamepadEventType::Connected(info) => {
+                info!("Gamepad {} connected: {}", event.gamepad.id, info.name)
+            }
+            GamepadEventType::Disconnected => info!("Gamepad {} disconnected", event.gamepad.id),
+            _ => {}
+        }
+    }
+}
+
+fn warn_conflicts(input_map: &InputMap) {
+    for action in Action::ALL {
+        let keys = input_map.keys(action).iter().map(|key| (format!("{key:?}"), input_map.conflicts(action, *key)));
+        let buttons = input_map
+            .buttons(action)
+            .iter()
+            .map(|button| (format!("{button:?}"), input_map.button_conflicts(action, *button)));
+        for (input, conflicts) in keys.chain(buttons) {
+            for other in conflicts {
+                // Each pair is found from both sides, only warn once
+                if action < other {
+                    warn!("{} is bound to both {} and {}", input, action.name(), other.name());
+                }
+            }
+        }
+    }
+}
+
+pub struct InputMapPlugin;
+
+impl Plugin for InputMapPlugin {
+    fn build(&self, app: &mut App) {
+        let mut input_map = load_config::<InputMap>(INPUT_MAP_FILE);
+        if let Some(user) = load_user_config::<InputMap>(INPUT_MAP_FILE) {
+            input_map.apply_user_bindings(user);
+        }
+        input_map.add_missing_defaults();
+        warn_conflicts(&input_map);
+
+        app.insert_resource(input_map)
+            .init_resource::<ActionState>()
+            .add_system_to_stage(CoreStage::PreUpdate, action_state_system.after(InputSystem))
+            .add_system(gamepad_connection_system);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binding_the_only_pause_key_elsewhere_is_rejected() {
+        let mut input_map = InputMap::default();
+        assert_eq!(input_map.bind(Action::Jump, KeyCode::Escape), None);
+        assert_eq!(input_map.keys(Action::Pause), [KeyCode::Escape]);
+        assert_eq!(input_map.keys(Action::Jump), [KeyCode::Space]);
+    }
+
+    #[test]
+    fn binding_a_pause_key_is_allowed_while_another_is_left() {
+        let mut input_map = InputMap::default();
+        input_map.bind(Action::Pause, KeyCode::P);
+        assert_eq!(input_map.bind(Action::Jump, KeyCode::Escape), Some(vec![Action::Pause]));
+        assert_eq!(input_map.keys(Action::Pause), [KeyCode::P]);
+    }
+
+    #[test]
+    fn sticks_inside_the_dead_zone_do_not_move() {
+        let input_map = InputMap { stick_dead_zone: 0.2, ..default() };
+        assert_eq!(input_map.apply_dead_zone(Vec2::new(0.1, -0.15)), Vec2::ZERO);
+        assert_eq!(input_map.apply_dead_zone(Vec2::new(0.0, 0.2)), Vec2::ZERO);
+        assert!((input_map.apply_dead_zone(Vec2::new(0.0, 0.6)).y - 0.5).abs() < 1e-6);
+    }
+}
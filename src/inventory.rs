@@ -0,0 +1,277 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{config::load_config, RootResource};
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Item {
+    pub id: String,
+    pub name: String,
+    // Most of this item one slot holds, Items::default_max_stack when not set
+    pub max_stack: Option<u32>,
+    // Block placed with this item in build mode
+    pub block: Option<RootResource>,
+}
+
+// All items from items.ron. Items that are not listed still work, named by their id.
+#[derive(Resource, Deserialize)]
+#[serde(default)]
+pub struct Items {
+    items: Vec<Item>,
+    default_max_stack: u32,
+}
+
+impl Default for Items {
+    fn default() -> Self {
+        Self { items: Vec::new(), default_max_stack: 99 }
+    }
+}
+
+impl Items {
+    pub fn get(&self, id: &str) -> Option<&Item> {
+        self.items.iter().find(|item| item.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Item> {
+        self.items.iter()
+    }
+
+    pub fn name<'a>(&'a self, id: &'a str) -> &'a str {
+        self.get(id).map_or(id, |item| item.name.as_str())
+    }
+
+    pub fn max_stack(&self, id: &str) -> u32 {
+        self.get(id).and_then(|item| item.max_stack).unwrap_or(self.default_max_stack).max(1)
+    }
+}
+
+#[derive(Clone)]
+pub struct ItemStack {
+    pub id: String,
+    pub count: u32,
+}
+
+// Sent for every entity whose inventory gained or lost items during the frame
+pub struct InventoryEvent {
+    pub entity: Entity,
+    pub id: String,
+    // Positive when items were added, negative when removed
+    pub change: i32,
+}
+
+// Fixed number of slots, each holding one stack of a single item
+#[derive(Component)]
+pub struct Inventory {
+    slots: Vec<Option<ItemStack>>,
+    // Changes since the last InventoryEvents were sent
+    changes: Vec<(String, i32)>,
+}
+
+impl Inventory {
+    pub fn new(slot_count: usize) -> Self {
+        Self { slots: vec![None; slot_count], changes: Vec::new() }
+    }
+
+    pub fn count(&self, id: &str) -> u32 {
+        self.stacks().filter(|stack| stack.id == id).map(|stack| stack.count).sum()
+    }
+
+    // Ids and total counts of everything held, in slot order
+    pub fn totals(&self) -> Vec<(String, u32)> {
+        let mut totals: Vec<(String, u32)> = Vec::new();
+        for stack in self.stacks() {
+            match totals.iter_mut().find(|(id, _)| *id == stack.id) {
+                Some((_, count)) => *count += stack.count,
+                None => totals.push((stack.id.clone(), stack.count)),
+            }
+        }
+        totals
+    }
+
+    // How many of the item still fit, on top of existing stacks and in empty slots
+    pub fn space_for(&self, id: &str, items: &Items) -> u32 {
+        let max_stack = items.max_stack(id);
+        self.slots
+            .iter()
+            .map(|slot| match slot {
+                Some(stack) if stack.id == id => max_stack.saturating_sub(stack.count),
+                Some(_) => 0,
+                None => max_stack,
+            })
+            .sum()
+    }
+
+    // Tops up existing stacks before filling empty slots. Returns how many did not fit.
+    pub fn add(&mut self, id: &str, count: u32, items: &Items) -> u32 {
+        let max_stack = items.max_stack(id);
+        let mut left = count;
+        for stack in self.slots.iter_mut().flatten().filter(|stack| stack.id == id) {
+            let moved = left.min(max_stack.saturating_sub(stack.count));
+            stack.count += moved;
+            left -= moved;
+        }
+        for slot in self.slots.iter_mut().filter(|slot| slot.is_none()) {
+            if left == 0 {
+                break;
+            }
+            let moved = left.min(max_stack);
+            *slot = Some(ItemStack { id: id.to_string(), count: moved });
+            left -= moved;
+        }
+        self.record(id, (count - left) as i32);
+        left
+    }
+
+    // Takes from the last stacks first. Returns how many were removed, at most count.
+    pub fn remove(&mut self, id: &str, count: u32) -> u32 {
+        let mut left = count;
+        for slot in self.slots.iter_mut().rev() {
+            let Some(stack) = slot.as_mut().filter(|stack| stack.id == id) else {
+                continue;
+            };
+            let moved = left.min(stack.count);
+            stack.count -= moved;
+            left -= moved;
+            if stack.count == 0 {
+                *slot = None;
+            }
+            if left == 0 {
+                break;
+            }
+        }
+        self.record(id, -((count - left) as i32));
+        count - left
+    }
+
+    // Moves as many as this inventory holds and the other has room for. Returns how many were moved.
+    // Nothing but the player has an inventory yet, chests and trading will use it.
+    #[allow(dead_code)]
+    pub fn transfer(&mut self, other: &mut Inventory, id: &str, count: u32, items: &Items) -> u32 {
+        let moved = count.min(self.count(id)).min(other.space_for(id, items));
+        self.remove(id, moved);
+        other.add(id, moved, items);
+        moved
+    }
+
+    fn stacks(&self) -> impl Iterator<Item = &ItemStack> {
+        self.slots.iter().flatten()
+    }
+
+    fn record(&mut self, id: &str, change: i32) {
+        if change != 0 {
+            self.changes.push((id.to_string(), change));
+        }
+    }
+}
+
+fn inventory_event_system(
+    mut query: Query<(Entity, &mut Inventory), Changed<Inventory>>,
+    mut inventory_events: EventWriter<InventoryEvent>,
+) {
+    for (entity, mut inventory) in query.iter_mut() {
+        // Draining the changes is not a change to the inventory itself
+        for (id, change) in inventory.bypass_change_detection().changes.drain(..) {
+            inventory_events.send(InventoryEvent { entity, id, change });
+        }
+    }
+}
+
+pub struct InventoryPlugin;
+
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_config::<Items>("items.ron"))
+            .add_event::<InventoryEvent>()
+            .add_system_to_stage(CoreStage::PostUpdate, inventory_event_system);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items() -> Items {
+        Items {
+            items: vec![Item { id: "wood_block".to_string(), max_stack: Some(5), ..default() }],
+            default_max_stack: 10,
+        }
+    }
+
+    #[test]
+    fn add_fills_stacks_up_to_the_limit() {
+        let items = items();
+        let mut inventory = Inventory::new(3);
+        assert_eq!(inventory.add("wood_block", 7, &items), 0);
+        assert_eq!(inventory.count("wood_block"), 7);
+        assert_eq!(inventory.slots.iter().flatten().map(|stack| stack.count).collect::<Vec<_>>(), [5, 2]);
+    }
+
+    #[test]
+    fn add_tops_up_existing_stacks_first() {
+        let items = items();
+        let mut inventory = Inventory::new(3);
+        inventory.add("sap", 4, &items);
+        inventory.add("bark", 1, &items);
+        inventory.add("sap", 4, &items);
+        assert_eq!(inventory.slots.iter().flatten().count(), 2);
+        assert_eq!(inventory.totals(), [("sap".to_string(), 8), ("bark".to_string(), 1)]);
+    }
+
+    #[test]
+    fn add_returns_what_does_not_fit() {
+        let items = items();
+        let mut inventory = Inventory::new(2);
+        inventory.add("sap", 1, &items);
+        assert_eq!(inventory.add("wood_block", 8, &items), 3);
+        assert_eq!(inventory.count("wood_block"), 5);
+        assert_eq!(inventory.space_for("wood_block", &items), 0);
+    }
+
+    #[test]
+    fn space_for_counts_partial_stacks_and_empty_slots() {
+        let items = items();
+        let mut inventory = Inventory::new(3);
+        inventory.add("wood_block", 3, &items);
+        inventory.add("sap", 1, &items);
+        assert_eq!(inventory.space_for("wood_block", &items), 2 + 5);
+        assert_eq!(inventory.space_for("sap", &items), 9 + 10);
+    }
+
+    #[test]
+    fn remove_takes_at_most_what_is_held() {
+        let items = items();
+        let mut inventory = Inventory::new(3);
+        inventory.add("wood_block", 7, &items);
+        assert_eq!(inventory.remove("wood_block", 3), 3);
+        assert_eq!(inventory.count("wood_block"), 4);
+        assert_eq!(inventory.remove("wood_block", 10), 4);
+        assert_eq!(inventory.count("wood_block"), 0);
+        assert!(inventory.slots.iter().all(Option::is_none));
+        assert_eq!(inventory.remove("sap", 1), 0);
+    }
+
+    #[test]
+    fn transfer_moves_only_what_fits() {
+        let items = items();
+        let mut from = Inventory::new(2);
+        from.add("wood_block", 8, &items);
+        let mut to = Inventory::new(1);
+        to.add("wood_block", 3, &items);
+        assert_eq!(from.transfer(&mut to, "wood_block", 6, &items), 2);
+        assert_eq!(from.count("wood_block"), 6);
+        assert_eq!(to.count("wood_block"), 5);
+        assert_eq!(from.transfer(&mut to, "wood_block", 1, &items), 0);
+        assert_eq!(to.transfer(&mut from, "sap", 1, &items), 0);
+    }
+
+    #[test]
+    fn changes_are_recorded_for_events() {
+        let items = items();
+        let mut inventory = Inventory::new(1);
+        inventory.add("wood_block", 8, &items);
+        inventory.remove("wood_block", 2);
+        inventory.remove("sap", 1);
+        assert_eq!(inventory.changes, [("wood_block".to_string(), 5), ("wood_block".to_string(), -2)]);
+    }
+}
@@ -1,25 +1,59 @@
-#[macro_use]
-extern crate lazy_static;
-
+mod atmosphere;
+mod billboard;
+mod block_atlas;
+mod build_mode;
+mod chunk_mesh;
+mod climbing;
+mod config;
 mod constants;
+mod controls_menu;
+mod crafting;
+mod culling;
+mod curvature;
+mod day_night;
+mod glow;
+mod grounded;
+mod input;
+mod inventory;
+mod occlusion;
+mod post_process;
 mod shaders;
+mod sprite_animation;
+mod tools;
 mod utils;
 mod vec3i;
+mod weather;
+mod wind;
 mod world_generation;
 
-use std::{f32::consts::PI};
+use std::f32::consts::{FRAC_PI_4, PI};
 
 use bevy::{
     audio::*,
     core_pipeline::bloom::BloomSettings,
+    pbr::NotShadowCaster,
     prelude::*,
-    utils::HashMap,
+    utils::{HashMap, HashSet},
 };
 use bevy_rapier3d::prelude::*;
+use serde::Deserialize;
+
+use billboard::{Billboard, BillboardMode};
+use build_mode::BuildMode;
+use chunk_mesh::chunk_of;
+use climbing::Climber;
 use constants::*;
+use culling::BendCulling;
+use grounded::Grounded;
+use input::{Action, ActionState};
+use inventory::{Inventory, InventoryEvent, Items};
+use occlusion::FadeOccluder;
 use shaders::CustomMaterial;
+use sprite_animation::{AnimationFrameEvent, SpriteAnimations, SpriteAnimator};
+use tools::{ToolCooldown, Toolbelt, Tools};
 use utils::*;
 use vec3i::*;
+use weather::Weather;
 use world_generation::*;
 
 #[derive(Component)]
@@ -33,47 +67,119 @@ impl Movement {
     }
 }
 
-#[derive(Component, Default)]
-struct Direction(Vec3);
-
-#[derive(Clone, Copy, Eq, PartialEq, Hash, Default)]
-enum CardinalDirection {
+// Eight-way facing of the player. Movement and building use all eight, the sprite and the
+// strike use the four directions there is art for, see clip_facing.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Default, Debug)]
+enum Facing {
+    Right,
+    UpRight,
     #[default] Up,
-    Down,
+    UpLeft,
     Left,
-    Right,
+    DownLeft,
+    Down,
+    DownRight,
+}
+
+impl Facing {
+    // Counter-clockwise when seen from above, starting from +X
+    const ALL: [Facing; 8] = [
+        Facing::Right,
+        Facing::UpRight,
+        Facing::Up,
+        Facing::UpLeft,
+        Facing::Left,
+        Facing::DownLeft,
+        Facing::Down,
+        Facing::DownRight,
+    ];
+
+    // Nearest facing to a direction on the ground, None for zero
+    fn from_vector(v: Vec3) -> Option<Facing> {
+        if v.x == 0.0 && v.z == 0.0 {
+            return None;
+        }
+        // Up is towards -Z
+        let angle = (-v.z).atan2(v.x);
+        let sector = (angle / FRAC_PI_4).round() as i32;
+        Some(Facing::ALL[sector.rem_euclid(8) as usize])
+    }
+
+    // Unit vector on the ground
+    fn vector(&self) -> Vec3 {
+        let index = Facing::ALL.iter().position(|f| f == self).unwrap_or_default();
+        let angle = index as f32 * FRAC_PI_4;
+        Vec3::new(angle.cos(), 0.0, -angle.sin())
+    }
+
+    // Facing of the clip that is shown. There is only art for four directions, so diagonals
+    // use the side clips and strikes go the way the sprite looks.
+    fn clip_facing(&self) -> Facing {
+        match self {
+            Facing::Right | Facing::UpRight | Facing::DownRight => Facing::Right,
+            Facing::Left | Facing::UpLeft | Facing::DownLeft => Facing::Left,
+            facing => *facing,
+        }
+    }
+
+    // Suffix of the directional animation clips
+    fn clip_suffix(&self) -> &'static str {
+        match self.clip_facing() {
+            Facing::Up => "up",
+            Facing::Down => "down",
+            Facing::Left => "left",
+            _ => "right",
+        }
+    }
 }
 
 #[derive(Component, Default)]
 struct Player {
-    sap: i32,
-    bark: i32,
-    wood: i32,
-    last_direction: CardinalDirection,
-    images: HashMap<CardinalDirection, Handle<Image>>,
-    strike_images: HashMap<CardinalDirection, Handle<Image>>,
-    strike_anim_timer: f32,
+    facing: Facing,
 }
 
+// Inventory contents in the corner of the screen
+#[derive(Component)]
+struct HudText;
+
 #[derive(Component)]
 struct CustomDamping(f32);
 
 #[derive(Component, Reflect, Default)]
 #[reflect(Component)]
 pub struct MainCamera {
-    bend_world: bool,
-    bending: f32,
     offset: Vec3,
     shake_intensity: f32,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Deserialize)]
 pub enum RootResource {
     Sap,
     Bark,
     Wood,
 }
 
+impl RootResource {
+    pub const ALL: [RootResource; 3] = [RootResource::Sap, RootResource::Bark, RootResource::Wood];
+
+    // Name of the block atlas layer, also the id of the item mining the block gives
+    pub fn name(&self) -> &'static str {
+        match self {
+            RootResource::Sap => "sap",
+            RootResource::Bark => "bark",
+            RootResource::Wood => "wood",
+        }
+    }
+
+    pub fn health(&self) -> i32 {
+        match self {
+            RootResource::Sap => 1,
+            RootResource::Bark => 2,
+            RootResource::Wood => 4,
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct Root {
     id: i64,
@@ -84,6 +190,19 @@ pub struct Root {
 #[derive(Component)]
 pub struct Health {
     health: i32,
+    max_health: i32,
+    last_hit: f32,
+}
+
+impl Health {
+    fn new(health: i32) -> Self {
+        Self { health, max_health: health, last_hit: NEVER_HIT }
+    }
+
+    // 0.0 when undamaged, 1.0 when destroyed
+    fn damage_ratio(&self) -> f32 {
+        1.0 - self.health.max(0) as f32 / self.max_health.max(1) as f32
+    }
 }
 
 struct DamageEvent {
@@ -92,20 +211,43 @@ struct DamageEvent {
     amount: i32,
 }
 
-struct AnimEvent {
-    direction: CardinalDirection,
-    is_strike: bool,
-}
-
 struct ParticleEvent {
     start: Vec3,
     vel: Vec3,
     col: Color,
+    // Uses the emissive sap material
+    glow: bool,
 }
 
 #[derive(Resource, Default)]
 pub struct BlockMap {
     entities: HashMap<Vec3i, Entity>,
+    dirty_chunks: HashSet<Vec3i>,
+    // Blocks with emissive materials, so glow lights do not have to search the whole map.
+    // Filled by glow, removed blocks are taken out here.
+    emissive: HashSet<Vec3i>,
+}
+
+impl BlockMap {
+    pub fn insert(&mut self, position: Vec3i, entity: Entity) {
+        self.entities.insert(position, entity);
+        self.mark_dirty(position);
+    }
+
+    pub fn remove(&mut self, position: &Vec3i) -> Option<Entity> {
+        let entity = self.entities.remove(position);
+        self.emissive.remove(position);
+        self.mark_dirty(*position);
+        entity
+    }
+
+    // Neighbouring chunks are marked too because their faces may become visible
+    pub fn mark_dirty(&mut self, position: Vec3i) {
+        self.dirty_chunks.insert(chunk_of(position));
+        for offset in [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)] {
+            self.dirty_chunks.insert(chunk_of(position + offset.into()));
+        }
+    }
 }
 
 #[derive(Resource, Default)]
@@ -115,6 +257,16 @@ pub struct AudioHandles {
     wood: Handle<AudioSource>,
 }
 
+impl AudioHandles {
+    fn block(&self, resource: RootResource) -> Handle<AudioSource> {
+        match resource {
+            RootResource::Sap => self.sap.clone(),
+            RootResource::Bark => self.bark.clone(),
+            RootResource::Wood => self.wood.clone(),
+        }
+    }
+}
+
 #[derive(Component, Reflect, Default)]
 #[reflect(Component)]
 pub struct BlockPosition(Vec3i);
@@ -130,6 +282,7 @@ struct ParticleHandles {
     mesh: Handle<Mesh>,
     bark_mat: Handle<CustomMaterial>,
     wood_mat: Handle<CustomMaterial>,
+    sap_mat: Handle<CustomMaterial>,
 }
 
 #[derive(Component)]
@@ -140,6 +293,8 @@ fn setup(
     mut meshes: ResMut<Assets<Mesh>>,
     mut custom_materials: ResMut<Assets<shaders::CustomMaterial>>,
     mut blockmap: ResMut<BlockMap>,
+    mut animations: ResMut<SpriteAnimations>,
+    tools: Res<Tools>,
     asset_server: Res<AssetServer>,
     mut audioHandles: ResMut<AudioHandles>,
     audio: Res<Audio>,
@@ -162,8 +317,6 @@ fn setup(
         },
         Name::new("MainCamera"),
         MainCamera {
-            bend_world: true,
-            bending: DEFAULT_BENDING,
             offset: INITIAL_CAMERA_OFFSET,
             shake_intensity: 0.0,
         },
@@ -177,9 +330,6 @@ fn setup(
     ));
 
     let ground_tex = asset_server.load("ground.png");
-    let sap_tex = asset_server.load("sap.png");
-    let bark_tex = asset_server.load("bark.png");
-    let wood_tex = asset_server.load("wood.png");
 
     let sap_sound: Handle<AudioSource> = asset_server.load("SapFast.ogg");
     let wood_sound: Handle<AudioSource> = asset_server.load("Wood.ogg");
@@ -189,18 +339,13 @@ fn setup(
     audioHandles.wood = wood_sound;
     audioHandles.bark = bark_sound;
 
-    let player_material = custom_materials.add(shaders::CustomMaterial {
-        time: 0.0,
-        bending: 0.1,
-        cam_position: Vec3::new(-2.0, 2.5, 5.0),
-        color: Vec3::new(1.0, 1.0, 1.0),
-        texture: asset_server.load("up.png"),
-        player_position: Vec3::ZERO,
-        viewport_size: Vec2::ZERO,
-    });
-    commands.spawn(
+    let player_material = custom_materials.add(
+        CustomMaterial::new(Color::WHITE, &asset_server.load("up.png"))
+            .with_alpha_mode(AlphaMode::Mask(SPRITE_ALPHA_CUTOFF)),
+    );
+    commands.spawn((
         TextBundle::from_section(
-            format_ui_text(0, 0, 0),
+            format_ui_text(&Inventory::new(0), &Items::default(), None),
             TextStyle {
                 font: asset_server.load("monogram.ttf"),
                 font_size: 30.0,
@@ -216,22 +361,15 @@ fn setup(
             },
             ..default()
         }),
-    );
+        HudText,
+    ));
 
     let cube_mesh = &meshes.add(Mesh::from(shape::Cube { size: 1.0 }));
     let plane_mesh = &meshes.add(Mesh::from(shape::Plane { size: 1.0 }));
 
-    let mut player_images = HashMap::new();
-    player_images.insert(CardinalDirection::Left, asset_server.load("right.png"));
-    player_images.insert(CardinalDirection::Right, asset_server.load("left.png"));
-    player_images.insert(CardinalDirection::Up, asset_server.load("up.png"));
-    player_images.insert(CardinalDirection::Down, asset_server.load("down.png"));
-
-    let mut player_strike_images = HashMap::new();
-    player_strike_images.insert(CardinalDirection::Left, asset_server.load("right_s.png"));
-    player_strike_images.insert(CardinalDirection::Right, asset_server.load("left_s.png"));
-    player_strike_images.insert(CardinalDirection::Up, asset_server.load("up_s.png"));
-    player_strike_images.insert(CardinalDirection::Down, asset_server.load("down_s.png"));
+    animations.load("player", &asset_server);
+    animations.load("bush", &asset_server);
+    animations.load("branch", &asset_server);
 
     // Create player entity
     commands.spawn((
@@ -239,48 +377,38 @@ fn setup(
             mesh: plane_mesh.clone(),
             material: player_material,
             transform: Transform::from_translation(Vec3::new(1.0, 15.0, 1.0))
-                .with_rotation(Quat::from_euler(EulerRot::XYZ, 0.5 * PI, PI, 0.0))
                 .with_scale(Vec3::new(1.0, 1.0, 2.0)),
             ..default()
         },
+        Billboard::new(BillboardMode::Cylindrical),
+        BendCulling::default(),
+        NotShadowCaster,
         Movement::new(30.0),
-        Player {
-            images: player_images,
-            strike_images: player_strike_images,
-            ..default()
-        },
+        Player::default(),
+        Inventory::new(PLAYER_INVENTORY_SLOTS),
+        SpriteAnimator::new("player", "idle_up"),
         Name::new("Cube"),
         RigidBody::Dynamic,
         Collider::ball(0.5),
         ExternalImpulse::default(),
         Velocity::default(),
+        Grounded::default(),
+        Climber::default(),
+        Toolbelt::new(&tools),
+        ToolCooldown::default(),
         MaxVelocity(8.0),
         CustomDamping(0.01), // Smaller value = stronger effect
         LockedAxes::ROTATION_LOCKED,
-        Direction::default(),
     ));
 
-    let material_map: &HashMap<RootResource, Handle<CustomMaterial>> = &[
-        (
-            RootResource::Sap,
-            custom_materials.add(CustomMaterial::new(Color::rgb(10.0, 5.0, 1.0), &sap_tex)),
-        ),
-        (
-            RootResource::Bark,
-            custom_materials.add(CustomMaterial::new(Color::WHITE, &bark_tex)),
-        ),
-        (
-            RootResource::Wood,
-            custom_materials.add(CustomMaterial::new(Color::WHITE, &wood_tex)),
-        ),
-    ]
-    .into_iter()
-    .collect();
-
     commands.insert_resource(ParticleHandles {
         mesh: cube_mesh.clone(),
         bark_mat: custom_materials.add(CustomMaterial::new(Color::WHITE, &ground_tex)),
         wood_mat: custom_materials.add(CustomMaterial::new(Color::WHITE, &ground_tex)),
+        sap_mat: custom_materials.add(
+            CustomMaterial::new(Color::rgb(1.0, 0.6, 0.2), &ground_tex)
+                .with_emissive(Color::rgb(3.0, 1.5, 0.3), 0.4),
+        ),
     });
 
     let ground_material = &custom_materials.add(CustomMaterial::new(Color::WHITE, &ground_tex));
@@ -288,9 +416,7 @@ fn setup(
     let height_chances = [0.1, 0.4, 0.7, 0.85, 0.95, 0.96, 0.98, 0.99];
 
     let mut gen = WorldGenerator {
-        cube_mesh,
         plane_mesh,
-        material_map,
         ground_material,
         rng: &mut rng,
         blockmap: &mut blockmap,
@@ -311,7 +437,11 @@ fn setup(
     gen.make_ground_plane(&mut commands);
 
     // Make random bushes
-    let bush_material = custom_materials.add(CustomMaterial::new(Color::WHITE, &asset_server.load("bush.png")));
+    let bush_material = custom_materials.add(
+        CustomMaterial::new(Color::WHITE, &asset_server.load("bush.png"))
+            .with_alpha_mode(AlphaMode::Mask(SPRITE_ALPHA_CUTOFF))
+            .with_sway(BUSH_SWAY),
+    );
     for _ in 0..250 {
         let location = random_location(gen.rng, LEVEL_MIN as i64, LEVEL_MAX as i64);
         commands.spawn((
@@ -319,18 +449,26 @@ fn setup(
                 mesh: plane_mesh.clone(),
                 material: bush_material.clone(),
                 transform: Transform::from_translation(location.into())
-                    .with_rotation(Quat::from_euler(EulerRot::XYZ, 0.5 * PI, PI, 0.0))
                     .with_scale(Vec3::new(1.2, 1.0, 1.2)),
                 ..default()
             },
-            Health{health:1},
+            Billboard::new(BillboardMode::Cylindrical),
+            BendCulling::default(),
+            NotShadowCaster,
+            FadeOccluder,
+            SpriteAnimator::new("bush", "idle"),
+            Health::new(1),
             Collider::ball(0.23),
             Name::new("Bush"),
         ));
     }
 
     // Make random branches
-    let bush_material = custom_materials.add(CustomMaterial::new(Color::WHITE, &asset_server.load("branch.png")));
+    let bush_material = custom_materials.add(
+        CustomMaterial::new(Color::WHITE, &asset_server.load("branch.png"))
+            .with_alpha_mode(AlphaMode::Mask(SPRITE_ALPHA_CUTOFF))
+            .with_sway(BRANCH_SWAY),
+    );
     for _ in 0..400 {
         let location = random_location(gen.rng, LEVEL_MIN as i64, LEVEL_MAX as i64) + (0, 10, 0).into();
         commands.spawn((
@@ -338,11 +476,15 @@ fn setup(
                 mesh: plane_mesh.clone(),
                 material: bush_material.clone(),
                 transform: Transform::from_translation(location.into())
-                    .with_rotation(Quat::from_euler(EulerRot::XYZ, 0.5 * PI, PI, 0.0))
                     .with_scale(Vec3::new(10.0, 1.0, 7.0)),
                 ..default()
             },
-            bevy::render::view::NoFrustumCulling,
+            // Branches hang above the camera target, so they face the camera plane fully
+            Billboard::new(BillboardMode::Spherical),
+            BendCulling::default(),
+            NotShadowCaster,
+            FadeOccluder,
+            SpriteAnimator::new("branch", "idle"),
             Name::new("Bush"),
         ));
     }
@@ -350,12 +492,21 @@ fn setup(
     audio.play_with_settings(music, PlaybackSettings { repeat: true, volume: 0.5, ..default() });
 }
 
-fn format_ui_text(sap: i32, bark: i32, wood: i32) -> String {
-    format!("Sap: {sap}\nBark: {bark}\nWood:{wood}")
+// recent is the item that changed last and by how much, shown next to its count
+fn format_ui_text(inventory: &Inventory, items: &Items, recent: Option<(&str, i32)>) -> String {
+    inventory
+        .totals()
+        .iter()
+        .map(|(id, count)| match recent {
+            Some((recent_id, change)) if recent_id == id => format!("{}: {count} ({change:+})", items.name(id)),
+            _ => format!("{}: {count}", items.name(id)),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 fn camera_system(
-    keyboard_input: Res<Input<KeyCode>>,
+    actions: Res<ActionState>,
     time: Res<Time>,
     mut query: Query<(&mut Transform, &mut MainCamera), Without<Player>>,
     player_query: Query<&Transform, With<Player>>,
@@ -381,91 +532,35 @@ fn camera_system(
         transform.translation = center + camera.offset + shake;
 
         let dir = (center - transform.translation).normalize();
-        if keyboard_input.pressed(KeyCode::Z) {
+        if actions.pressed(Action::ZoomIn) {
             camera.offset += dir * 5.0 * time.delta_seconds();
         }
-        if keyboard_input.pressed(KeyCode::X) {
+        if actions.pressed(Action::ZoomOut) {
             camera.offset -= dir * 5.0 * time.delta_seconds();
         }
     }
 }
 
 fn movement_system(
-    keyboard_input: Res<Input<KeyCode>>,
+    actions: Res<ActionState>,
     time: Res<Time>,
-    mut query: Query<(&mut ExternalImpulse, &mut Direction, &Movement, &Transform, &mut Player)>,
-    not_player_query: Query<Entity, Without<Player>>,
+    mut query: Query<(&mut ExternalImpulse, &Movement, &mut Player)>,
     mut max_vel_query: Query<(&mut Velocity, &MaxVelocity)>,
-    mut custom_materials: ResMut<Assets<CustomMaterial>>,
-    rapier_context: Res<RapierContext>,
-    mut anim_events: EventWriter<AnimEvent>,
 ) {
-    let ray_hit = |pos, dir| {
-        let mut hit = false;
-        rapier_context.intersections_with_ray(
-            pos,
-            dir,
-            1.0,
-            false,
-            QueryFilter::new(),
-            |entity, _| {
-                if not_player_query.get(entity).is_ok() {
-                    hit = true;
-                    return false;
-                }
-                true // true = continue searching
-            },
-        );
-        hit
-    };
-
     for (mut vel, max) in max_vel_query.iter_mut() {
         if vel.linvel.length() > max.0 {
             vel.linvel = vel.linvel.normalize() * max.0;
         }
     }
 
-    for (mut external, mut dir, movement, transform, mut player) in query.iter_mut() {
-        // Sum vectors from directions that are pressed
-        let movement_dir = KEYS
-            .iter()
-            .filter_map(|(key, v)| keyboard_input.pressed(*key).then_some(v))
-            .sum::<Vec3>()
-            .normalize_or_zero();
+    for (mut external, movement, mut player) in query.iter_mut() {
+        // Shorter than 1.0 when a gamepad stick is only partly deflected
+        let movement_dir = actions.movement();
 
-        if movement_dir != Vec3::ZERO {
-            dir.0 = movement_dir;
+        if let Some(facing) = Facing::from_vector(movement_dir) {
+            player.facing = facing;
         }
         external.impulse = movement_dir * movement.speed * time.delta_seconds();
-
-        // Jumping
-        if keyboard_input.just_pressed(KeyCode::Space)
-            && ray_hit(transform.translation, (0.0, -1.0, 0.0).into())
-        {
-            external.impulse += Vec3::new(0.0, 4.0, 0.0);
-        }
-
-        let cdir =
-            if movement_dir == (0.0, 0.0, -1.0).into() {
-                Some(CardinalDirection::Up)
-            }
-            else if movement_dir == (0.0, 0.0, 1.0).into() {
-                Some(CardinalDirection::Down)
-            }
-            else if movement_dir == (-1.0, 0.0, 0.0).into() {
-                Some(CardinalDirection::Left)
-            }
-            else if movement_dir == (1.0, 0.0, 0.0).into() {
-                Some(CardinalDirection::Right)
-            }
-            else {
-                None
-            };
-
-        if let Some(dir) = cdir {
-            player.last_direction = dir;
-            anim_events.send(AnimEvent { direction: dir, is_strike: false });
-        }
     }
 }
 
@@ -486,29 +581,31 @@ fn damage_system(
     mut particle_events: EventWriter<ParticleEvent>,
     mut query: Query<(&mut Health, &Transform)>,
     root_query: Query<(&Root, &BlockPosition)>,
-    mut player_query: Query<&mut Player>,
+    mut inventory_query: Query<&mut Inventory>,
     mut camera_query: Query<&mut MainCamera>,
     mut blockmap: ResMut<BlockMap>,
     audio_handles: Res<AudioHandles>,
     audio: Res<Audio>,
+    weather: Res<Weather>,
+    items: Res<Items>,
+    time: Res<Time>,
     mut commands: Commands,
 ) {
     for ev in damage_events.iter() {
         if let Ok((mut health, target_transform)) = query.get_mut(ev.target_entity) {
             health.health -= ev.amount;
+            // Same clock as the shader's globals.time
+            health.last_hit = time.elapsed_seconds_wrapped();
 
-            particle_events.send(ParticleEvent { start: target_transform.translation, vel: Vec3::ZERO, col: Color::WHITE });
+            let root_tuple = root_query.get(ev.target_entity);
+            let glow = matches!(root_tuple, Ok((root, _)) if root.resource == RootResource::Sap);
 
-            camera_query.single_mut().shake_intensity += 0.02;
+            particle_events.send(ParticleEvent { start: target_transform.translation, vel: Vec3::ZERO, col: Color::WHITE, glow });
 
-            let root_tuple = root_query.get(ev.target_entity);
+            camera_query.single_mut().shake_intensity += 0.02;
 
             if let Ok((root, _)) = root_tuple {
-                match root.resource {
-                    RootResource::Sap => audio.play(audio_handles.sap.clone()),
-                    RootResource::Bark => audio.play(audio_handles.bark.clone()),
-                    RootResource::Wood => audio.play(audio_handles.wood.clone()),
-                };
+                audio.play(audio_handles.block(root.resource));
             }
 
             if health.health <= 0 {
@@ -516,20 +613,15 @@ fn damage_system(
                 camera_query.single_mut().shake_intensity += 0.1;
 
                 if let Ok((root, block_pos)) = root_tuple {
-                    blockmap.entities.remove_entry(&block_pos.0);
-
-                    if let Ok(mut player) = player_query.get_mut(ev.attacker) {
-                        match root.resource {
-                            RootResource::Sap => {
-                                player.sap += root.mineable;
-                                audio.play(audio_handles.sap.clone());
-                            }
-                            RootResource::Bark => {
-                                player.bark += root.mineable;
-                                audio.play(audio_handles.bark.clone());
-                            }
-                            RootResource::Wood => player.wood += root.mineable,
+                    blockmap.remove(&block_pos.0);
+
+                    if let Ok(mut inventory) = inventory_query.get_mut(ev.attacker) {
+                        let amount = (root.mineable as f32 * weather.yield_of(root.resource)).round() as u32;
+                        let left = inventory.add(root.resource.name(), amount, &items);
+                        if left > 0 {
+                            info!("Inventory full, lost {left} {}", items.name(root.resource.name()));
                         }
+                        audio.play(audio_handles.block(root.resource));
                     }
                 }
             }
@@ -571,8 +663,8 @@ fn collapse_trunks_system(
             let entity = blockmap.entities.get(&old).copied();
             match entity {
                 Some(entity) => {
-                    blockmap.entities.remove_entry(&old);
-                    blockmap.entities.insert(new, entity);
+                    blockmap.remove(&old);
+                    blockmap.insert(new, entity);
                 }
                 None => {
                     print!("Wheres the freaking entity?!?");
@@ -582,92 +674,143 @@ fn collapse_trunks_system(
     }
 }
 
-fn ui_count_system(mut query: Query<&mut Text>, player_query: Query<&Player, Changed<Player>>) {
-    for player in &player_query {
-        for mut text in query.iter_mut() {
-            text.sections.first_mut().unwrap().value =
-                format_ui_text(player.sap, player.bark, player.wood)
-        }
+struct RecentChange {
+    id: String,
+    change: i32,
+    time_left: f32,
+}
+
+fn ui_count_system(
+    mut query: Query<&mut Text, With<HudText>>,
+    inventory_query: Query<(Entity, &Inventory), With<Player>>,
+    mut inventory_events: EventReader<InventoryEvent>,
+    mut recent: Local<Option<RecentChange>>,
+    items: Res<Items>,
+    time: Res<Time>,
+) {
+    let Ok((player, inventory)) = inventory_query.get_single() else {
+        return;
+    };
+
+    let mut changed = false;
+    for ev in inventory_events.iter().filter(|ev| ev.entity == player) {
+        // Changes to the item that is already shown add up
+        let change = match recent.as_ref() {
+            Some(previous) if previous.id == ev.id => previous.change + ev.change,
+            _ => ev.change,
+        };
+        *recent = Some(RecentChange { id: ev.id.clone(), change, time_left: HUD_CHANGE_TIME });
+        changed = true;
+    }
+    let expired = recent.as_mut().map_or(false, |recent| {
+        recent.time_left -= time.delta_seconds();
+        recent.time_left <= 0.0
+    });
+    if expired {
+        *recent = None;
+        changed = true;
+    }
+    if !changed {
+        return;
+    }
+
+    let value = format_ui_text(inventory, &items, recent.as_ref().map(|recent| (recent.id.as_str(), recent.change)));
+    for mut text in query.iter_mut() {
+        text.sections.first_mut().unwrap().value = value.clone();
     }
 }
 
 fn player_attack_system(
-    query: Query<(Entity, &Transform, &Direction, &Player)>,
-    mut enemy_query: Query<Entity, (With<Health>, Without<Player>)>,
+    mut query: Query<(&Player, &Toolbelt, &mut ToolCooldown, &mut SpriteAnimator)>,
+    actions: Res<ActionState>,
+    tools: Res<Tools>,
+    build_mode: Res<BuildMode>,
+) {
+    // In build mode Attack places blocks instead
+    if actions.just_pressed(Action::Attack) && !build_mode.active {
+        for (player, toolbelt, mut cooldown, mut animator) in query.iter_mut() {
+            if cooldown.try_strike(toolbelt.tool(&tools)) {
+                animator.restart(&format!("strike_{}", player.facing.clip_suffix()));
+            }
+        }
+    }
+}
+
+// Damage is dealt on the impact frame of the strike animation
+fn player_strike_system(
+    mut query: Query<(Entity, &Transform, &Player, &mut Toolbelt)>,
+    enemy_query: Query<Option<&Root>, (With<Health>, Without<Player>)>,
+    mut frame_events: EventReader<AnimationFrameEvent>,
     mut damage_events: EventWriter<DamageEvent>,
-    mut anim_events: EventWriter<AnimEvent>,
     rapier_context: Res<RapierContext>,
-    keyboard_input: Res<Input<KeyCode>>,
+    tools: Res<Tools>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::C) {
-        for (player_entity, player_transform, dir, player) in query.iter() {
-
-            anim_events.send(AnimEvent { direction: player.last_direction, is_strike: true });
-
+    for ev in frame_events.iter() {
+        if ev.name != "impact" {
+            continue;
+        }
+        if let Ok((player_entity, player_transform, player, mut toolbelt)) = query.get_mut(ev.entity) {
+            let tool = toolbelt.tool(&tools);
             let ray_pos = player_transform.translation;
-            let ray_dir = dir.0;
+            // Same direction as the strike sprite
+            let ray_dir = player.facing.clip_facing().vector();
+
+            // Nearest thing with Health along the ray, hits are not reported in order
+            let mut target = None;
+            let mut nearest = f32::MAX;
             rapier_context.intersections_with_ray(
                 ray_pos,
                 ray_dir,
-                1.0,
+                tool.reach,
                 false,
                 QueryFilter::new(),
                 |entity, intersection| {
-                    // Callback called on each collider hit by the ray.
-                    let hit_point = intersection.point;
-                    let hit_normal = intersection.normal;
-
-                    // Check if player hit anything that has Health
-                    if let Ok(enemy_entity) = enemy_query.get_mut(entity) {
-                        damage_events.send(DamageEvent {
-                            target_entity: enemy_entity,
-                            attacker: player_entity,
-                            amount: 1,
-                        });
-                        return false;
+                    if let Ok(root) = enemy_query.get(entity) {
+                        if intersection.toi < nearest {
+                            nearest = intersection.toi;
+                            target = Some((entity, root.map(|root| root.resource)));
+                        }
                     }
-
                     true // true = continue searching
                 },
             );
-        }
-    }
-}
-
-fn animation_system(
-    mut query: Query<(&Handle<CustomMaterial>, &mut Player)>,
-    mut anim_events: EventReader<AnimEvent>,
-    mut custom_materials: ResMut<Assets<CustomMaterial>>,
-    time: Res<Time>,
-) {
-    for ev in anim_events.iter() {
-        for (mat, mut player) in query.iter_mut() {
-            if let Some(mat) = custom_materials.get_mut(mat) {
-
-                if ev.is_strike {
-                    player.strike_anim_timer = 0.2;
-                    mat.texture = player.strike_images.get(&ev.direction).unwrap().clone();
-                }
-                else {
-                    mat.texture = player.images.get(&ev.direction).unwrap().clone();
-                }
 
+            let Some((target_entity, resource)) = target else {
+                continue;
+            };
+            let amount = tool.damage_against(resource);
+            if amount <= 0 {
+                continue;
+            }
+            damage_events.send(DamageEvent { target_entity, attacker: player_entity, amount });
+            if let Some(broken) = toolbelt.wear() {
+                info!("{} broke", tools.get(&broken.id).map_or(broken.id.as_str(), |tool| tool.name.as_str()));
             }
         }
     }
+}
 
-    for (mat, mut player) in query.iter_mut() {
-        if player.strike_anim_timer > 0.0 {
-            player.strike_anim_timer -= time.delta_seconds();
-
-            if player.strike_anim_timer <= 0.0 {
-                if let Some(mat) = custom_materials.get_mut(mat) {
-                    if let Some(img) = player.images.get(&player.last_direction) {
-                        mat.texture = img.clone();
-                    }
-                }
-            }
+// Picks the player clip from the movement state
+fn player_animation_system(mut query: Query<(&Player, &Velocity, &Climber, &mut SpriteAnimator)>) {
+    for (player, velocity, climber, mut animator) in query.iter_mut() {
+        // Strikes play to the end
+        if animator.clip().starts_with("strike") && !animator.finished() {
+            continue;
         }
+
+        let horizontal_speed = Vec2::new(velocity.linvel.x, velocity.linvel.z).length();
+        let state = if climber.climbing {
+            "climb"
+        } else if velocity.linvel.y > AIRBORNE_ANIMATION_SPEED {
+            "jump"
+        } else if velocity.linvel.y < -AIRBORNE_ANIMATION_SPEED {
+            "fall"
+        } else if horizontal_speed > WALK_ANIMATION_SPEED {
+            "walk"
+        } else {
+            "idle"
+        };
+        animator.play(&format!("{state}_{}", player.facing.clip_suffix()));
     }
 }
 
@@ -693,7 +836,11 @@ fn particle_system(
         commands.spawn((
             MaterialMeshBundle {
                 mesh: particle_handles.mesh.clone(),
-                material: particle_handles.wood_mat.clone(),
+                material: if ev.glow {
+                    particle_handles.sap_mat.clone()
+                } else {
+                    particle_handles.wood_mat.clone()
+                },
                 transform: Transform::from_translation(ev.start)
                     .with_rotation(Quat::from_euler(EulerRot::XYZ, 0.5 * PI, PI, 0.0))
                     .with_scale(Vec3::new(0.1, 0.1, 0.1)),
@@ -723,16 +870,34 @@ fn main() {
             window: WindowDescriptor { title: "Sap from the roots".to_string(), ..default() },
             ..default()
         }))
-        .add_plugin(MaterialPlugin::<shaders::CustomMaterial>::default())
         // .add_plugin(bevy_editor_pls::EditorPlugin)
         // .add_plugin(bevy::diagnostic::FrameTimeDiagnosticsPlugin)
         // .add_plugin(bevy::diagnostic::EntityCountDiagnosticsPlugin)
         .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
         .add_plugin(shaders::ShaderPlugin)
+        .add_plugin(input::InputMapPlugin)
+        .add_plugin(grounded::GroundedPlugin)
+        .add_plugin(climbing::ClimbingPlugin)
+        .add_plugin(inventory::InventoryPlugin)
+        .add_plugin(tools::ToolsPlugin)
+        .add_plugin(crafting::CraftingPlugin)
+        .add_plugin(build_mode::BuildModePlugin)
+        .add_plugin(controls_menu::ControlsMenuPlugin)
+        .add_plugin(atmosphere::AtmospherePlugin)
+        .add_plugin(block_atlas::BlockAtlasPlugin)
+        .add_plugin(chunk_mesh::ChunkMeshPlugin)
+        .add_plugin(culling::CullingPlugin)
+        .add_plugin(day_night::DayNightPlugin)
+        .add_plugin(weather::WeatherPlugin)
+        .add_plugin(wind::WindPlugin)
+        .add_plugin(glow::GlowPlugin)
+        .add_plugin(occlusion::OcclusionPlugin)
+        .add_plugin(curvature::CurvaturePlugin)
+        .add_plugin(billboard::BillboardPlugin)
+        .add_plugin(sprite_animation::SpriteAnimationPlugin)
+        .add_plugin(post_process::PostProcessPlugin)
         .add_event::<DamageEvent>()
-        .add_event::<AnimEvent>()
         .add_event::<ParticleEvent>()
-        .insert_resource(ClearColor(Color::rgb(27.0 / 255.0, 28.0 / 255.0, 17.0 / 255.0)))
         .insert_resource(BlockMap::default())
         .insert_resource(AudioHandles::default())
         .insert_resource(ParticleHandles::default())
@@ -744,11 +909,36 @@ fn main() {
         .add_system(ui_count_system)
         .add_system(custom_damping_system)
         .add_system(player_attack_system)
+        .add_system(player_strike_system)
         .add_system(damage_system)
-        .add_system(animation_system)
+        .add_system(player_animation_system)
         .add_system(camera_shake_system)
         .add_system(particle_system)
         .register_type::<MainCamera>() // Only needed for in-game inspector
         .register_type::<BlockPosition>()
         .run();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn facing_round_trips_through_vectors() {
+        for facing in Facing::ALL {
+            assert_eq!(Facing::from_vector(facing.vector()), Some(facing));
+        }
+    }
+
+    #[test]
+    fn strikes_go_the_way_the_clip_looks() {
+        for facing in Facing::ALL {
+            let shown = Facing::from_vector(facing.vector()).unwrap();
+            let strike = Facing::from_vector(shown.clip_facing().vector()).unwrap();
+            assert_eq!(strike.clip_suffix(), shown.clip_suffix());
+            assert_eq!(strike, shown.clip_facing());
+        }
+        assert_eq!(Facing::UpRight.clip_suffix(), "right");
+        assert_eq!(Facing::DownLeft.clip_facing().vector(), Facing::Left.vector());
+    }
+}
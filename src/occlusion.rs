@@ -0,0 +1,120 @@
+use bevy::prelude::*;
+
+use crate::{
+    billboard::billboard_material_system,
+    curvature::WorldCurvature,
+    shaders::{CustomMaterial, MaterialVariants, ShaderGlobals},
+    BlockMap, MainCamera, Player,
+};
+
+// Seconds to fade occluding surfaces in or out
+const OCCLUSION_FADE_TIME: f32 = 0.25;
+// Size of the see-through area around the player, relative to the screen height
+const OCCLUSION_SCREEN_RADIUS: f32 = 0.18;
+// Step length when looking for blocks on the line from the camera to the player
+const OCCLUSION_STEP: f32 = 0.25;
+// Part of the line next to the player that is ignored, so blocks beside the player do not count
+const OCCLUSION_PLAYER_MARGIN: f32 = 0.75;
+
+// Sprite that dithers away while it hides the player. Blocks always do this.
+#[derive(Component, Default)]
+pub struct FadeOccluder;
+
+#[derive(Resource, Default)]
+struct Occlusion {
+    // 0.0 when the player is visible, 1.0 when occluding surfaces are fully faded
+    fade: f32,
+}
+
+// Distance from a point to the segment from start to end, and how far along the segment it is from 0 to 1
+fn segment_distance(point: Vec3, start: Vec3, end: Vec3) -> (f32, f32) {
+    let line = end - start;
+    let t = ((point - start).dot(line) / line.length_squared().max(0.0001)).clamp(0.0, 1.0);
+    (point.distance(start + line * t), t)
+}
+
+fn is_player_occluded(
+    camera_position: Vec3,
+    player_position: Vec3,
+    blockmap: &BlockMap,
+    curvature: &WorldCurvature,
+    occluder_query: &Query<&Transform, (With<FadeOccluder>, Without<Player>, Without<MainCamera>)>,
+) -> bool {
+    // The player is a billboard, so it is drawn bent by its origin
+    let drawn_player = curvature.bend(player_position, camera_position);
+    let line = drawn_player - camera_position;
+    let length = line.length() - OCCLUSION_PLAYER_MARGIN;
+    if length <= 0.0 {
+        return false;
+    }
+
+    // Blocks are checked where they are drawn, so the line is mapped back to the unbent world
+    let steps = (length / OCCLUSION_STEP) as usize;
+    let direction = line.normalize();
+    for i in 0..=steps {
+        let world = curvature.unbend(camera_position + direction * (i as f32 * OCCLUSION_STEP), camera_position);
+        let block = world.round();
+        if blockmap.entities.contains_key(&(block.x as i64, block.y as i64, block.z as i64).into()) {
+            return true;
+        }
+    }
+
+    // Sprites are tested as spheres around their drawn origin
+    let end_t = length / line.length();
+    occluder_query.iter().any(|transform| {
+        let radius = transform.scale.x.max(transform.scale.z) * 0.5;
+        let drawn = curvature.bend(transform.translation, camera_position);
+        let (distance, t) = segment_distance(drawn, camera_position, drawn_player);
+        t < end_t && distance < radius
+    })
+}
+
+fn occlusion_system(
+    camera_query: Query<&Transform, With<MainCamera>>,
+    player_query: Query<&Transform, (With<Player>, Without<MainCamera>)>,
+    occluder_query: Query<&Transform, (With<FadeOccluder>, Without<Player>, Without<MainCamera>)>,
+    blockmap: Res<BlockMap>,
+    curvature: Res<WorldCurvature>,
+    mut occlusion: ResMut<Occlusion>,
+    mut globals: ResMut<ShaderGlobals>,
+    time: Res<Time>,
+) {
+    let (Ok(camera_t), Ok(player_t)) = (camera_query.get_single(), player_query.get_single()) else {
+        return;
+    };
+
+    let occluded = is_player_occluded(
+        camera_t.translation,
+        player_t.translation,
+        &blockmap,
+        &curvature,
+        &occluder_query,
+    );
+    let step = time.delta_seconds() / OCCLUSION_FADE_TIME;
+    let target = if occluded { 1.0 } else { 0.0 };
+    occlusion.fade += (target - occlusion.fade).clamp(-step, step);
+
+    globals.occlusion_fade = occlusion.fade;
+    globals.occlusion_radius = OCCLUSION_SCREEN_RADIUS;
+}
+
+fn occluder_material_system(
+    mut query: Query<&mut Handle<CustomMaterial>, Added<FadeOccluder>>,
+    mut custom_materials: ResMut<Assets<CustomMaterial>>,
+    mut variants: Local<MaterialVariants>,
+) {
+    for mut handle in query.iter_mut() {
+        variants.apply(&mut handle, &mut custom_materials, |mat| mat.fade_occluding = true);
+    }
+}
+
+pub struct OcclusionPlugin;
+
+impl Plugin for OcclusionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Occlusion::default())
+            .add_system(occlusion_system)
+            // Occluding billboards get a copy of the billboard material
+            .add_system(occluder_material_system.after(billboard_material_system));
+    }
+}
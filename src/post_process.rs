@@ -0,0 +1,262 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        camera::RenderTarget,
+        render_resource::{
+            AsBindGroup, Extent3d, ShaderRef, ShaderType, TextureDescriptor, TextureDimension,
+            TextureFormat, TextureUsages,
+        },
+        texture::BevyDefault,
+        view::RenderLayers,
+    },
+    sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle, Mesh2dHandle},
+    utils::HashMap,
+    window::WindowResized,
+};
+use serde::Deserialize;
+
+use crate::{
+    config::load_config,
+    day_night::DayNight,
+    weather::{Weather, WeatherKind},
+    MainCamera,
+};
+
+// Only the post-process camera and its quad are on this layer
+const POST_PROCESS_LAYER: u8 = 1;
+
+// Colour grading and screen effects applied after the scene has been rendered.
+// LUTs are 16x16x16 strips of 256x16 pixels, blue selects the 16 pixel wide slice.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct PostProcessConfig {
+    day_lut: String,
+    night_lut: String,
+    // Blended in while the weather is in one of these states
+    weather_luts: HashMap<WeatherKind, String>,
+    // How much the weather LUT replaces the time of day grading once faded in
+    weather_amount: f32,
+    film_grain: f32,
+    // Offset of the red and blue channels at the screen edges, relative to the screen size
+    chromatic_aberration: f32,
+}
+
+impl Default for PostProcessConfig {
+    fn default() -> Self {
+        Self {
+            day_lut: "luts/neutral.png".to_string(),
+            night_lut: "luts/neutral.png".to_string(),
+            weather_luts: HashMap::default(),
+            weather_amount: 1.0,
+            film_grain: 0.0,
+            chromatic_aberration: 0.0,
+        }
+    }
+}
+
+#[derive(Resource)]
+struct PostProcess {
+    config: PostProcessConfig,
+    image: Handle<Image>,
+    quad: Handle<Mesh>,
+    material: Handle<PostProcessMaterial>,
+    weather_luts: HashMap<WeatherKind, Handle<Image>>,
+}
+
+#[derive(ShaderType, Clone, Default)]
+struct PostProcessUniform {
+    // 0.0 uses the night LUT, 1.0 the day LUT
+    daylight: f32,
+    weather_amount: f32,
+    film_grain: f32,
+    chromatic_aberration: f32,
+    time: f32,
+}
+
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "7d3c2a4e-8f5b-4c1d-b6e9-0a2f4d6c8e1b"]
+struct PostProcessMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    source: Handle<Image>,
+    #[texture(2)]
+    #[sampler(3)]
+    day_lut: Handle<Image>,
+    #[texture(4)]
+    #[sampler(5)]
+    night_lut: Handle<Image>,
+    #[texture(6)]
+    #[sampler(7)]
+    weather_lut: Handle<Image>,
+    #[uniform(8)]
+    settings: PostProcessUniform,
+}
+
+impl Material2d for PostProcessMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/post_process.wgsl".into()
+    }
+}
+
+fn render_target_image(width: u32, height: u32) -> Image {
+    let size = Extent3d { width, height, ..default() };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("post_process_source"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::bevy_default(),
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+        },
+        ..default()
+    };
+    // Fills the image with zeros
+    image.resize(size);
+    image
+}
+
+// Runs after setup so the MainCamera exists. It is redirected into an image that the
+// post-process camera draws onto a full screen quad. The UI is drawn by the post-process camera.
+fn setup_post_process(
+    mut commands: Commands,
+    mut camera_query: Query<(&mut Camera, &mut UiCameraConfig), With<MainCamera>>,
+    windows: Res<Windows>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<PostProcessMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    let Some(window) = windows.get_primary() else {
+        return;
+    };
+    let config: PostProcessConfig = load_config("post_process.ron");
+
+    let image = images.add(render_target_image(window.physical_width(), window.physical_height()));
+    for (mut camera, mut ui_config) in camera_query.iter_mut() {
+        camera.target = RenderTarget::Image(image.clone());
+        ui_config.show_ui = false;
+    }
+
+    let day_lut: Handle<Image> = asset_server.load(config.day_lut.as_str());
+    let weather_luts = config
+        .weather_luts
+        .iter()
+        .map(|(kind, path)| (*kind, asset_server.load(path.as_str())))
+        .collect();
+    let material = materials.add(PostProcessMaterial {
+        source: image.clone(),
+        day_lut: day_lut.clone(),
+        night_lut: asset_server.load(config.night_lut.as_str()),
+        // Replaced when a weather with its own LUT starts
+        weather_lut: day_lut,
+        settings: PostProcessUniform::default(),
+    });
+    let quad = meshes.add(Mesh::from(shape::Quad::new(Vec2::new(window.width(), window.height()))));
+
+    let layer = RenderLayers::layer(POST_PROCESS_LAYER);
+    commands.spawn((
+        MaterialMesh2dBundle {
+            mesh: Mesh2dHandle(quad.clone()),
+            material: material.clone(),
+            ..default()
+        },
+        layer,
+        Name::new("PostProcessQuad"),
+    ));
+    commands.spawn((
+        Camera2dBundle {
+            camera: Camera {
+                // Renders after the main camera
+                priority: 1,
+                ..default()
+            },
+            ..default()
+        },
+        layer,
+        Name::new("PostProcessCamera"),
+    ));
+
+    commands.insert_resource(PostProcess { config, image, quad, material, weather_luts });
+}
+
+fn resize_post_process_system(
+    mut resize_events: EventReader<WindowResized>,
+    windows: Res<Windows>,
+    post_process: Option<Res<PostProcess>>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let Some(post_process) = post_process else {
+        return;
+    };
+    let Some(window) = windows.get_primary() else {
+        return;
+    };
+    if !resize_events.iter().any(|ev| ev.id == window.id()) {
+        return;
+    }
+
+    if let Some(image) = images.get_mut(&post_process.image) {
+        image.resize(Extent3d {
+            width: window.physical_width().max(1),
+            height: window.physical_height().max(1),
+            ..default()
+        });
+    }
+    if let Some(mesh) = meshes.get_mut(&post_process.quad) {
+        *mesh = Mesh::from(shape::Quad::new(Vec2::new(window.width(), window.height())));
+    }
+}
+
+fn post_process_system(
+    post_process: Option<Res<PostProcess>>,
+    mut materials: ResMut<Assets<PostProcessMaterial>>,
+    day_night: Res<DayNight>,
+    weather: Res<Weather>,
+    time: Res<Time>,
+) {
+    let Some(post_process) = post_process else {
+        return;
+    };
+    let Some(material) = materials.get_mut(&post_process.material) else {
+        return;
+    };
+
+    // Fades towards the LUT of the current weather, or out of the one of the previous weather
+    let current = post_process.weather_luts.get(&weather.current);
+    let previous = post_process.weather_luts.get(&weather.previous());
+    let (weather_lut, weather_amount) = match (current, previous) {
+        (Some(lut), _) => (Some(lut), weather.blend()),
+        (None, Some(lut)) => (Some(lut), 1.0 - weather.blend()),
+        (None, None) => (None, 0.0),
+    };
+    if let Some(lut) = weather_lut {
+        if material.weather_lut != *lut {
+            material.weather_lut = lut.clone();
+        }
+    }
+
+    material.settings = PostProcessUniform {
+        daylight: day_night.daylight(),
+        weather_amount: weather_amount * post_process.config.weather_amount,
+        film_grain: post_process.config.film_grain,
+        chromatic_aberration: post_process.config.chromatic_aberration,
+        time: time.elapsed_seconds_wrapped(),
+    };
+}
+
+pub struct PostProcessPlugin;
+
+impl Plugin for PostProcessPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(Material2dPlugin::<PostProcessMaterial>::default())
+            .add_startup_system_to_stage(StartupStage::PostStartup, setup_post_process)
+            .add_system(resize_post_process_system)
+            .add_system(post_process_system);
+    }
+}
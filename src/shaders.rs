@@ -1,43 +1,431 @@
+use std::{num::NonZeroU64, sync::OnceLock};
+
 use bevy::{
+    core_pipeline::core_3d::{AlphaMask3d, Opaque3d, Transparent3d},
+    ecs::system::{lifetimeless::SRes, SystemParamItem},
+    pbr::{
+        DrawMesh, MaterialPipeline, MaterialPipelineKey, SetMaterialBindGroup, SetMeshBindGroup,
+        SetMeshViewBindGroup,
+    },
     prelude::*,
     reflect::TypeUuid,
-    render::render_resource::{AsBindGroup, ShaderRef},
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        mesh::{MeshVertexAttribute, MeshVertexBufferLayout},
+        render_asset::RenderAssets,
+        render_phase::{
+            CachedRenderPipelinePhaseItem, DrawFunctions, EntityPhaseItem, EntityRenderCommand,
+            RenderCommandResult, RenderCommandState, SetItemPipeline, TrackedRenderPass,
+        },
+        render_resource::{
+            encase::{self, internal::WriteInto},
+            AsBindGroup, AsBindGroupError, BindGroup, BindGroupDescriptor, BindGroupEntry,
+            BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+            BufferBindingType, BufferInitDescriptor, BufferUsages, OwnedBindingResource,
+            PreparedBindGroup, RenderPipelineDescriptor, SamplerBindingType, ShaderRef,
+            ShaderStages, ShaderType, SpecializedMeshPipelineError, TextureSampleType,
+            StorageBuffer, TextureViewDimension, UniformBuffer, VertexFormat,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        texture::FallbackImage,
+        RenderApp, RenderStage,
+    },
+    utils::HashMap,
 };
 
-use crate::{MainCamera, Player};
+use crate::Player;
+
+// Per-frame values shared by all custom materials.
+// Time, camera position and viewport size are read from Bevy's view bindings instead.
+#[derive(Resource, ExtractResource, ShaderType, Clone, Default)]
+pub struct ShaderGlobals {
+    pub rolling_log_bending: f32,
+    pub spherical_bending: f32,
+    pub player_position: Vec3,
+    pub fog_min: f32,
+    pub fog_max: f32,
+    pub fog_color: Vec3,
+    pub ambient: Vec3,
+    pub vignette_radius: f32,
+    pub vignette_softness: f32,
+    pub light_direction: Vec3,
+    pub sun_color: Vec3,
+    pub fill_light: f32,
+    // Brightness multiplier for glowing block layers
+    pub glow_intensity: f32,
+    // Direction times strength in the xz plane
+    pub wind: Vec2,
+    pub wind_frequency: f32,
+    pub wind_push_radius: f32,
+    pub wind_push_strength: f32,
+    // Radians per second of pulsing emissive surfaces
+    pub glow_pulse_speed: f32,
+    // Nearby emissive blocks, position in xyz and radius in w
+    pub glow_lights: [Vec4; MAX_GLOW_LIGHTS],
+    // Colour in rgb
+    pub glow_light_colors: [Vec4; MAX_GLOW_LIGHTS],
+    pub glow_light_count: u32,
+    // How far surfaces hiding the player are dithered away, from 0 to 1
+    pub occlusion_fade: f32,
+    // Size of the faded area relative to the screen height
+    pub occlusion_radius: f32,
+}
+
+// Damage of one block. Filled in by chunk_mesh for every damaged block.
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct BlockDamageEntry {
+    pub position: IVec3,
+    // Ratio of lost health, 0 marks an empty entry in the table
+    pub damage: f32,
+    pub last_hit: f32,
+}
+
+// Looked up by the block shader by position, so hits do not rebuild the chunk meshes
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct BlockDamage {
+    pub blocks: Vec<BlockDamageEntry>,
+}
+
+const MIN_BLOCK_DAMAGE_ENTRIES: usize = 256;
+
+// Hash table of the damaged blocks with linear probing. It is kept at most half full so
+// lookups in the shader stay short and always reach an empty entry.
+#[derive(ShaderType, Default)]
+struct BlockDamageTable {
+    // Entry count minus one, the count is a power of two
+    mask: u32,
+    #[size(runtime)]
+    entries: Vec<BlockDamageEntry>,
+}
+
+// Same as block_damage_slot in custom.wgsl, the tests at the end of this file pin its values
+fn block_damage_slot(position: IVec3, mask: u32) -> u32 {
+    let hash = (position.x as u32).wrapping_mul(73856093)
+        ^ (position.y as u32).wrapping_mul(19349663)
+        ^ (position.z as u32).wrapping_mul(83492791);
+    hash & mask
+}
 
-#[derive(AsBindGroup, Debug, Clone, TypeUuid)]
+impl BlockDamageTable {
+    fn new(blocks: &[BlockDamageEntry]) -> Self {
+        let size = (blocks.len() * 2).next_power_of_two().max(MIN_BLOCK_DAMAGE_ENTRIES);
+        let mask = size as u32 - 1;
+        let mut entries = vec![BlockDamageEntry::default(); size];
+        for block in blocks.iter().filter(|block| block.damage > 0.0) {
+            let mut slot = block_damage_slot(block.position, mask);
+            while entries[slot as usize].damage > 0.0 {
+                slot = (slot + 1) & mask;
+            }
+            entries[slot as usize] = *block;
+        }
+        Self { mask, entries }
+    }
+}
+
+// The globals are bound in their own group after Bevy's view, material and mesh groups, so
+// they are uploaded once per frame no matter how many materials exist.
+const GLOBALS_GROUP: usize = 3;
+
+#[derive(Resource)]
+struct ShaderGlobalsBuffer {
+    buffer: UniformBuffer<ShaderGlobals>,
+    // Bound next to the globals
+    block_damage: StorageBuffer<BlockDamageTable>,
+    // Rebuilt every frame in the queue stage
+    bind_group: Option<BindGroup>,
+}
+
+// Created once when the plugin is built. Pipeline specialization cannot reach render
+// resources, so the materials read it from here.
+static GLOBALS_LAYOUT: OnceLock<BindGroupLayout> = OnceLock::new();
+
+#[derive(ShaderType)]
+struct CustomMaterialUniform {
+    color: Vec3,
+    // Texels with a lower alpha are discarded
+    alpha_cutoff: f32,
+    // Non-zero when the texture alpha is written out for blending
+    alpha_blend: u32,
+    uv_rect: Vec4,
+    bend_from_origin: u32,
+    sway: f32,
+    emissive: Vec3,
+    emissive_pulse: f32,
+    fade_occluding: u32,
+}
+
+#[derive(Debug, Clone, TypeUuid)]
 #[uuid = "f690fdae-d598-45ab-8225-97e2a3f056e0"]
 pub struct CustomMaterial {
-    #[uniform(0)]
-    pub time: f32,
-    #[uniform(1)]
-    pub bending: f32,
-    #[uniform(2)]
-    pub cam_position: Vec3,
-    #[uniform(3)]
     pub color: Vec3,
-    #[texture(4)]
-    #[sampler(5)]
     pub texture: Handle<Image>,
-    #[uniform(6)]
-    pub player_position: Vec3,
-    #[uniform(7)]
-    pub viewport_size: Vec2,
+    pub alpha_mode: AlphaMode,
+    // Part of the texture that is shown, offset in xy and size in zw. Used for sprite sheets.
+    pub uv_rect: Vec4,
+    // Bends the mesh as a whole by the bending at its origin instead of per vertex. Used by billboards.
+    pub bend_from_origin: bool,
+    // How far plane_mesh sprites move with the wind, 0 disables it
+    pub sway: f32,
+    pub emissive: Vec3,
+    // How much the emissive light dims and brightens over time, from 0 to 1
+    pub emissive_pulse: f32,
+    // Dithers away near the player while it hides the player. Set for FadeOccluder sprites.
+    pub fade_occluding: bool,
 }
 
 impl CustomMaterial {
     pub fn new(value: Color, tex: &Handle<Image>) -> Self {
         Self {
-            time: 0.0,
-            bending: 0.1,
-            cam_position: Vec3::ZERO,
             color: Vec3::new(value.r(), value.g(), value.b()),
             texture: tex.clone(),
-            player_position: Vec3::ZERO,
-            viewport_size: Vec2::ZERO,
+            alpha_mode: AlphaMode::Opaque,
+            uv_rect: Vec4::new(0.0, 0.0, 1.0, 1.0),
+            bend_from_origin: false,
+            sway: 0.0,
+            emissive: Vec3::ZERO,
+            emissive_pulse: 0.0,
+            fade_occluding: false,
         }
     }
+
+    pub fn with_alpha_mode(mut self, alpha_mode: AlphaMode) -> Self {
+        self.alpha_mode = alpha_mode;
+        self
+    }
+
+    pub fn with_sway(mut self, sway: f32) -> Self {
+        self.sway = sway;
+        self
+    }
+
+    pub fn with_emissive(mut self, emissive: Color, pulse: f32) -> Self {
+        self.emissive = Vec3::new(emissive.r(), emissive.g(), emissive.b());
+        self.emissive_pulse = pulse;
+        self
+    }
+}
+
+// Copies of shared materials with one setting changed. Entities are switched over to the copy,
+// so the original stays as it is for everything else using it.
+#[derive(Default)]
+pub struct MaterialVariants(HashMap<Handle<CustomMaterial>, Handle<CustomMaterial>>);
+
+impl MaterialVariants {
+    pub fn apply(
+        &mut self,
+        handle: &mut Handle<CustomMaterial>,
+        custom_materials: &mut Assets<CustomMaterial>,
+        change: impl Fn(&mut CustomMaterial),
+    ) {
+        if let Some(variant) = self.0.get(&*handle) {
+            *handle = variant.clone();
+            return;
+        }
+        let Some(mut material) = custom_materials.get(handle).cloned() else {
+            return;
+        };
+        change(&mut material);
+        let variant = custom_materials.add(material);
+        self.0.insert(handle.clone(), variant.clone());
+        *handle = variant;
+    }
+}
+
+pub const MAX_BLOCK_LAYERS: usize = 16;
+
+pub const MAX_GLOW_LIGHTS: usize = 8;
+
+pub const ATTRIBUTE_BLOCK_LAYER: MeshVertexAttribute =
+    MeshVertexAttribute::new("BlockLayer", 988540917, VertexFormat::Uint32);
+
+#[derive(ShaderType)]
+struct BlockMaterialUniform {
+    layer_colors: [Vec4; MAX_BLOCK_LAYERS],
+    layer_emissive: [Vec4; MAX_BLOCK_LAYERS],
+}
+
+// Shared by all voxel blocks. The texture is an array with one layer per block type,
+// selected by the layer vertex attribute of the chunk meshes.
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "3b0ac5c1-4f4e-4d1b-9a55-6c1f5b0de7a2"]
+pub struct BlockMaterial {
+    pub layer_colors: [Vec4; MAX_BLOCK_LAYERS],
+    // Emissive colour in rgb and pulse amount in a
+    pub layer_emissive: [Vec4; MAX_BLOCK_LAYERS],
+    pub texture: Handle<Image>,
+}
+
+fn globals_bind_group_layout(render_device: &RenderDevice) -> &'static BindGroupLayout {
+    GLOBALS_LAYOUT.get_or_init(|| {
+        render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("shader_globals_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX_FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(ShaderGlobals::min_size()),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(BlockDamageTable::min_size()),
+                    },
+                    count: None,
+                },
+            ],
+        })
+    })
+}
+
+// Bindings shared by the custom materials: material uniform, texture and sampler
+fn material_bind_group<T: ShaderType + WriteInto>(
+    uniform_value: &T,
+    texture: &Handle<Image>,
+    layout: &BindGroupLayout,
+    render_device: &RenderDevice,
+    images: &RenderAssets<Image>,
+) -> Result<(Vec<OwnedBindingResource>, BindGroup), AsBindGroupError> {
+    let image = images.get(texture).ok_or(AsBindGroupError::RetryNextUpdate)?;
+
+    let mut uniform = encase::UniformBuffer::new(Vec::new());
+    uniform.write(uniform_value).unwrap();
+    let material_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("custom_material"),
+        usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+        contents: uniform.as_ref(),
+    });
+
+    let bindings = vec![
+        OwnedBindingResource::Buffer(material_buffer),
+        OwnedBindingResource::TextureView(image.texture_view.clone()),
+        OwnedBindingResource::Sampler(image.sampler.clone()),
+    ];
+    let entries: Vec<BindGroupEntry> = bindings
+        .iter()
+        .enumerate()
+        .map(|(i, binding)| BindGroupEntry { binding: i as u32, resource: binding.get_binding() })
+        .collect();
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("custom_material_bind_group"),
+        layout,
+        entries: &entries,
+    });
+
+    Ok((bindings, bind_group))
+}
+
+fn material_bind_group_layout(
+    render_device: &RenderDevice,
+    uniform_size: NonZeroU64,
+    view_dimension: TextureViewDimension,
+) -> BindGroupLayout {
+    render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("custom_material_layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX_FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(uniform_size),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    multisampled: false,
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+    })
+}
+
+impl AsBindGroup for CustomMaterial {
+    type Data = ();
+
+    fn as_bind_group(
+        &self,
+        layout: &BindGroupLayout,
+        render_device: &RenderDevice,
+        images: &RenderAssets<Image>,
+        _fallback_image: &FallbackImage,
+    ) -> Result<PreparedBindGroup<Self>, AsBindGroupError> {
+        let (alpha_cutoff, alpha_blend) = match self.alpha_mode {
+            AlphaMode::Opaque => (0.0, 0),
+            AlphaMode::Mask(cutoff) => (cutoff, 0),
+            AlphaMode::Blend => (0.0, 1),
+        };
+        let uniform = CustomMaterialUniform {
+            color: self.color,
+            alpha_cutoff,
+            alpha_blend,
+            uv_rect: self.uv_rect,
+            bend_from_origin: self.bend_from_origin as u32,
+            sway: self.sway,
+            emissive: self.emissive,
+            emissive_pulse: self.emissive_pulse,
+            fade_occluding: self.fade_occluding as u32,
+        };
+        let (bindings, bind_group) =
+            material_bind_group(&uniform, &self.texture, layout, render_device, images)?;
+        Ok(PreparedBindGroup { bindings, bind_group, data: () })
+    }
+
+    fn bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+        material_bind_group_layout(
+            render_device,
+            CustomMaterialUniform::min_size(),
+            TextureViewDimension::D2,
+        )
+    }
+}
+
+impl AsBindGroup for BlockMaterial {
+    type Data = ();
+
+    fn as_bind_group(
+        &self,
+        layout: &BindGroupLayout,
+        render_device: &RenderDevice,
+        images: &RenderAssets<Image>,
+        _fallback_image: &FallbackImage,
+    ) -> Result<PreparedBindGroup<Self>, AsBindGroupError> {
+        let uniform = BlockMaterialUniform {
+            layer_colors: self.layer_colors,
+            layer_emissive: self.layer_emissive,
+        };
+        let (bindings, bind_group) =
+            material_bind_group(&uniform, &self.texture, layout, render_device, images)?;
+        Ok(PreparedBindGroup { bindings, bind_group, data: () })
+    }
+
+    fn bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+        material_bind_group_layout(
+            render_device,
+            BlockMaterialUniform::min_size(),
+            TextureViewDimension::D2Array,
+        )
+    }
 }
 
 impl Material for CustomMaterial {
@@ -47,44 +435,229 @@ impl Material for CustomMaterial {
     fn fragment_shader() -> ShaderRef {
         "shaders/custom.wgsl".into()
     }
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        add_globals_layout(descriptor);
+        Ok(())
+    }
 }
 
-fn update_shaders(
-    query: Query<(&Transform, &MainCamera)>,
-    player_query: Query<&Transform, With<Player>>,
-    mut custom_materials: ResMut<Assets<CustomMaterial>>,
-    time: Res<Time>,
-    windows: Res<Windows>,
-) {
-    let window = windows.get_primary().unwrap();
-    let win_size = Vec2::new(window.width() * window.scale_factor() as f32, window.height() * window.scale_factor() as f32);
+impl Material for BlockMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/custom.wgsl".into()
+    }
+    fn fragment_shader() -> ShaderRef {
+        "shaders/custom.wgsl".into()
+    }
 
-    let mut player_pos =
-        if let Ok(player_t) = player_query.get_single() {
-            player_t.translation
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        add_globals_layout(descriptor);
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(2),
+            ATTRIBUTE_BLOCK_LAYER.at_shader_location(7),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        descriptor.vertex.shader_defs.push("BLOCK_ATLAS".into());
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader_defs.push("BLOCK_ATLAS".into());
         }
-        else {
-            Vec3::ZERO
-        };
+        Ok(())
+    }
+}
 
-    if let Ok((camera_transform, camera)) = query.get_single() {
-        for mat in custom_materials.iter_mut() {
-            mat.1.time = time.elapsed_seconds();
-            mat.1.cam_position = camera_transform.translation;
-            mat.1.bending = if camera.bend_world { camera.bending } else { 0.0 };
-            mat.1.player_position = player_pos;
-            mat.1.viewport_size = win_size;
-        }
+// Bevy's material pipeline holds the view, material and mesh layouts, the globals come after them
+fn add_globals_layout(descriptor: &mut RenderPipelineDescriptor) {
+    let globals_layout = GLOBALS_LAYOUT
+        .get()
+        .expect("the globals layout is created when ShaderPlugin is built");
+    if let Some(layouts) = descriptor.layout.as_mut() {
+        layouts.insert(GLOBALS_GROUP, globals_layout.clone());
     }
+}
 
+// MaterialPlugin's own draw function, its type is private so it is spelled out here
+type DrawMaterial<M> = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMaterialBindGroup<M, 1>,
+    SetMeshBindGroup<2>,
+    DrawMesh,
+);
+
+type DrawMaterialWithGlobals<M> = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMaterialBindGroup<M, 1>,
+    SetMeshBindGroup<2>,
+    SetShaderGlobalsBindGroup<GLOBALS_GROUP>,
+    DrawMesh,
+);
+
+struct SetShaderGlobalsBindGroup<const I: usize>;
+
+impl<const I: usize> EntityRenderCommand for SetShaderGlobalsBindGroup<I> {
+    type Param = SRes<ShaderGlobalsBuffer>;
+
+    fn render<'w>(
+        _view: Entity,
+        _item: Entity,
+        globals_buffer: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(bind_group) = globals_buffer.into_inner().bind_group.as_ref() else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_bind_group(I, bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+// Queue systems look the draw function up by the DrawMaterial type, so registering another
+// one under that type replaces it for every material of type M
+fn replace_material_draw<M: Material, P: EntityPhaseItem + CachedRenderPipelinePhaseItem>(
+    render_app: &mut App,
+) {
+    let draw_function =
+        RenderCommandState::<P, DrawMaterialWithGlobals<M>>::new(&mut render_app.world);
+    render_app
+        .world
+        .resource::<DrawFunctions<P>>()
+        .write()
+        .add_with::<DrawMaterial<M>, _>(draw_function);
+}
+
+fn replace_material_draws<M: Material>(render_app: &mut App) {
+    replace_material_draw::<M, Opaque3d>(render_app);
+    replace_material_draw::<M, AlphaMask3d>(render_app);
+    replace_material_draw::<M, Transparent3d>(render_app);
+}
+
+fn update_shaders(
+    player_query: Query<&Transform, With<Player>>,
+    mut globals: ResMut<ShaderGlobals>,
+) {
     if let Ok(player_t) = player_query.get_single() {
+        globals.player_position = player_t.translation;
+    }
+}
+
+fn prepare_shader_globals(
+    globals: Option<Res<ShaderGlobals>>,
+    mut globals_buffer: ResMut<ShaderGlobalsBuffer>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    if let Some(globals) = globals {
+        globals_buffer.buffer.set(globals.clone());
+        globals_buffer.buffer.write_buffer(&render_device, &render_queue);
+    }
+}
+
+fn prepare_block_damage(
+    block_damage: Option<Res<BlockDamage>>,
+    mut globals_buffer: ResMut<ShaderGlobalsBuffer>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let changed = block_damage.as_ref().map_or(false, |block_damage| block_damage.is_changed());
+    // The empty table is uploaded before the first damage so the bind group can be made
+    if changed || globals_buffer.block_damage.buffer().is_none() {
+        let blocks = block_damage.as_ref().map_or(&[][..], |block_damage| &block_damage.blocks);
+        globals_buffer.block_damage.set(BlockDamageTable::new(blocks));
+        globals_buffer.block_damage.write_buffer(&render_device, &render_queue);
     }
 }
 
+fn queue_shader_globals_bind_group(
+    mut globals_buffer: ResMut<ShaderGlobalsBuffer>,
+    render_device: Res<RenderDevice>,
+) {
+    let (Some(globals), Some(block_damage)) =
+        (globals_buffer.buffer.binding(), globals_buffer.block_damage.binding())
+    else {
+        return;
+    };
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("shader_globals_bind_group"),
+        layout: globals_bind_group_layout(&render_device),
+        entries: &[
+            BindGroupEntry { binding: 0, resource: globals },
+            BindGroupEntry { binding: 1, resource: block_damage },
+        ],
+    });
+    globals_buffer.bind_group = Some(bind_group);
+}
+
 pub struct ShaderPlugin;
 
 impl Plugin for ShaderPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.add_system(update_shaders);
+        // The material plugins go first, their draw functions are replaced below
+        app.add_plugin(MaterialPlugin::<CustomMaterial>::default())
+            .add_plugin(MaterialPlugin::<BlockMaterial>::default())
+            .insert_resource(ShaderGlobals::default())
+            .add_plugin(ExtractResourcePlugin::<ShaderGlobals>::default())
+            .init_resource::<BlockDamage>()
+            .add_plugin(ExtractResourcePlugin::<BlockDamage>::default())
+            .add_system(update_shaders);
+
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            let render_device = render_app.world.resource::<RenderDevice>();
+            let mut buffer = UniformBuffer::from(ShaderGlobals::default());
+            buffer.set_label(Some("shader_globals"));
+            let mut block_damage = StorageBuffer::default();
+            block_damage.set_label(Some("block_damage"));
+            // Created before any material pipeline is specialized
+            globals_bind_group_layout(render_device);
+
+            render_app
+                .insert_resource(ShaderGlobalsBuffer { buffer, block_damage, bind_group: None })
+                .add_system_to_stage(RenderStage::Prepare, prepare_shader_globals)
+                .add_system_to_stage(RenderStage::Prepare, prepare_block_damage)
+                .add_system_to_stage(RenderStage::Queue, queue_shader_globals_bind_group);
+            replace_material_draws::<CustomMaterial>(render_app);
+            replace_material_draws::<BlockMaterial>(render_app);
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // custom.wgsl hashes blocks the same way, change both together
+    #[test]
+    fn block_damage_slots_are_pinned() {
+        assert_eq!(block_damage_slot(IVec3::new(0, 0, 0), 255), 0);
+        assert_eq!(block_damage_slot(IVec3::new(1, 0, 0), 255), 93);
+        assert_eq!(block_damage_slot(IVec3::new(1, 2, 3), 255), 70);
+        assert_eq!(block_damage_slot(IVec3::new(1, 2, 3), 1023), 582);
+        assert_eq!(block_damage_slot(IVec3::new(-1, 5, -7), 255), 71);
+        assert_eq!(block_damage_slot(IVec3::new(12, -3, 40), 1023), 743);
+    }
+
+    #[test]
+    fn block_damage_table_probes_past_taken_slots() {
+        let block = |x| BlockDamageEntry { position: IVec3::new(x, 0, 0), damage: 0.5, last_hit: 0.0 };
+        // 0 and 256 share slot 0 with the smallest table
+        let table = BlockDamageTable::new(&[block(0), block(256)]);
+        assert_eq!(table.mask, MIN_BLOCK_DAMAGE_ENTRIES as u32 - 1);
+        assert_eq!(table.entries[0].position.x, 0);
+        assert_eq!(table.entries[1].position.x, 256);
+    }
+}
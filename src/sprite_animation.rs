@@ -0,0 +1,223 @@
+use bevy::{prelude::*, utils::HashMap};
+use serde::Deserialize;
+
+use crate::{config::load_config, shaders::CustomMaterial};
+
+#[derive(Deserialize, Default)]
+struct AnimationFile {
+    sheets: HashMap<String, SheetData>,
+    clips: HashMap<String, ClipData>,
+}
+
+// Image split into a grid of equally sized frames, numbered row by row
+#[derive(Deserialize)]
+struct SheetData {
+    image: String,
+    #[serde(default = "one")]
+    columns: u32,
+    #[serde(default = "one")]
+    rows: u32,
+}
+
+#[derive(Deserialize)]
+struct ClipData {
+    frames: Vec<FrameData>,
+    #[serde(default = "yes")]
+    looping: bool,
+}
+
+#[derive(Deserialize)]
+struct FrameData {
+    sheet: String,
+    #[serde(default)]
+    index: u32,
+    duration: f32,
+    // Sent as an AnimationFrameEvent when the frame is shown
+    #[serde(default)]
+    event: Option<String>,
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn yes() -> bool {
+    true
+}
+
+struct SpriteFrame {
+    image: Handle<Image>,
+    // Offset in xy and size in zw of the frame inside its sheet
+    uv_rect: Vec4,
+    duration: f32,
+    event: Option<String>,
+}
+
+struct SpriteClip {
+    frames: Vec<SpriteFrame>,
+    looping: bool,
+}
+
+// Clips of every loaded animation file, by file name and clip name
+#[derive(Resource, Default)]
+pub struct SpriteAnimations {
+    sets: HashMap<String, HashMap<String, SpriteClip>>,
+}
+
+impl SpriteAnimations {
+    // Reads assets/animations/<name>.ron unless it has been loaded already
+    pub fn load(&mut self, name: &str, asset_server: &AssetServer) {
+        if self.sets.contains_key(name) {
+            return;
+        }
+
+        let file: AnimationFile = load_config(&format!("animations/{name}.ron"));
+        let mut clips = HashMap::new();
+        for (clip_name, clip) in file.clips {
+            let mut frames = Vec::with_capacity(clip.frames.len());
+            for frame in clip.frames {
+                let Some(sheet) = file.sheets.get(&frame.sheet) else {
+                    warn!("Animation {name}/{clip_name} uses unknown sheet {}", frame.sheet);
+                    continue;
+                };
+                let columns = sheet.columns.max(1);
+                let rows = sheet.rows.max(1);
+                let size = Vec2::new(1.0 / columns as f32, 1.0 / rows as f32);
+                let offset = Vec2::new((frame.index % columns) as f32, (frame.index / columns) as f32) * size;
+                frames.push(SpriteFrame {
+                    image: asset_server.load(sheet.image.as_str()),
+                    uv_rect: Vec4::new(offset.x, offset.y, size.x, size.y),
+                    duration: frame.duration.max(0.001),
+                    event: frame.event,
+                });
+            }
+            if !frames.is_empty() {
+                clips.insert(clip_name, SpriteClip { frames, looping: clip.looping });
+            }
+        }
+        self.sets.insert(name.to_string(), clips);
+    }
+
+    fn clip(&self, set: &str, clip: &str) -> Option<&SpriteClip> {
+        self.sets.get(set)?.get(clip)
+    }
+}
+
+// Plays clips from an animation file on the CustomMaterial of the entity
+#[derive(Component)]
+pub struct SpriteAnimator {
+    set: String,
+    clip: String,
+    frame: usize,
+    elapsed: f32,
+    finished: bool,
+    // Set when a clip starts, its first frame is shown on the next update
+    started: bool,
+}
+
+impl SpriteAnimator {
+    pub fn new(set: &str, clip: &str) -> Self {
+        Self {
+            set: set.to_string(),
+            clip: clip.to_string(),
+            frame: 0,
+            elapsed: 0.0,
+            finished: false,
+            started: true,
+        }
+    }
+
+    // Keeps the current clip running if it is already playing
+    pub fn play(&mut self, clip: &str) {
+        if self.clip != clip {
+            self.restart(clip);
+        }
+    }
+
+    pub fn restart(&mut self, clip: &str) {
+        self.clip = clip.to_string();
+        self.frame = 0;
+        self.elapsed = 0.0;
+        self.finished = false;
+        self.started = true;
+    }
+
+    pub fn clip(&self) -> &str {
+        &self.clip
+    }
+
+    // True once a clip that does not loop has shown its last frame
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+}
+
+pub struct AnimationFrameEvent {
+    pub entity: Entity,
+    pub name: String,
+}
+
+fn sprite_animation_system(
+    mut query: Query<(Entity, &mut SpriteAnimator, &Handle<CustomMaterial>)>,
+    animations: Res<SpriteAnimations>,
+    mut custom_materials: ResMut<Assets<CustomMaterial>>,
+    mut frame_events: EventWriter<AnimationFrameEvent>,
+    time: Res<Time>,
+) {
+    for (entity, mut animator, material) in query.iter_mut() {
+        let Some(clip) = animations.clip(&animator.set, &animator.clip) else {
+            continue;
+        };
+
+        let mut entered = Vec::new();
+        if animator.started {
+            animator.started = false;
+            entered.push(0);
+        } else if !animator.finished {
+            animator.elapsed += time.delta_seconds();
+            while animator.elapsed >= clip.frames[animator.frame].duration {
+                animator.elapsed -= clip.frames[animator.frame].duration;
+                if animator.frame + 1 < clip.frames.len() {
+                    animator.frame += 1;
+                } else if clip.looping {
+                    animator.frame = 0;
+                } else {
+                    animator.finished = true;
+                    break;
+                }
+                entered.push(animator.frame);
+            }
+        }
+        if entered.is_empty() {
+            continue;
+        }
+
+        for index in entered {
+            if let Some(name) = &clip.frames[index].event {
+                frame_events.send(AnimationFrameEvent { entity, name: name.clone() });
+            }
+        }
+
+        // Materials can be shared between props, so they are only touched when the frame differs
+        let frame = &clip.frames[animator.frame];
+        let unchanged = custom_materials
+            .get(material)
+            .map_or(true, |mat| mat.texture == frame.image && mat.uv_rect == frame.uv_rect);
+        if !unchanged {
+            if let Some(mat) = custom_materials.get_mut(material) {
+                mat.texture = frame.image.clone();
+                mat.uv_rect = frame.uv_rect;
+            }
+        }
+    }
+}
+
+pub struct SpriteAnimationPlugin;
+
+impl Plugin for SpriteAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SpriteAnimations::default())
+            .add_event::<AnimationFrameEvent>()
+            .add_system(sprite_animation_system);
+    }
+}
@@ -0,0 +1,317 @@
+use bevy::{pbr::NotShadowCaster, prelude::*, utils::HashMap};
+use serde::Deserialize;
+
+use crate::{
+    build_mode::BuildMode,
+    config::load_config,
+    constants::SPRITE_ALPHA_CUTOFF,
+    input::{Action, ActionState},
+    shaders::CustomMaterial,
+    RootResource,
+};
+
+// Id of the bare hand, which every Toolbelt keeps
+const HAND_ID: &str = "hand";
+
+// id and name are required, a tool without them would be mistaken for the hand
+#[derive(Deserialize, Clone)]
+pub struct Tool {
+    pub id: String,
+    pub name: String,
+    // Damage per hit against blocks of each resource, default_damage for anything else
+    #[serde(default)]
+    pub damage: HashMap<RootResource, i32>,
+    #[serde(default = "default_tool_damage")]
+    pub default_damage: i32,
+    // Length of the strike ray
+    #[serde(default = "default_tool_reach")]
+    pub reach: f32,
+    // Seconds between strikes
+    #[serde(default = "default_tool_cooldown")]
+    pub cooldown: f32,
+    // Hits that deal damage before the tool breaks, None never breaks
+    #[serde(default)]
+    pub durability: Option<u32>,
+    // Drawn over the player sprite
+    #[serde(default)]
+    pub image: Option<String>,
+}
+
+fn default_tool_damage() -> i32 {
+    1
+}
+
+fn default_tool_reach() -> f32 {
+    1.0
+}
+
+fn default_tool_cooldown() -> f32 {
+    0.3
+}
+
+impl Default for Tool {
+    fn default() -> Self {
+        Self {
+            id: HAND_ID.to_string(),
+            name: "Hand".to_string(),
+            damage: HashMap::default(),
+            default_damage: default_tool_damage(),
+            reach: default_tool_reach(),
+            cooldown: default_tool_cooldown(),
+            durability: None,
+            image: None,
+        }
+    }
+}
+
+impl Tool {
+    pub fn damage_against(&self, resource: Option<RootResource>) -> i32 {
+        resource
+            .and_then(|resource| self.damage.get(&resource).copied())
+            .unwrap_or(self.default_damage)
+    }
+}
+
+// All tools from tools.ron, always including the hand
+#[derive(Resource, Deserialize)]
+#[serde(default)]
+pub struct Tools {
+    tools: Vec<Tool>,
+    // Ids of the tools the player starts with besides the hand
+    starting_tools: Vec<String>,
+}
+
+impl Default for Tools {
+    fn default() -> Self {
+        Self { tools: vec![Tool::default()], starting_tools: Vec::new() }
+    }
+}
+
+impl Tools {
+    pub fn get(&self, id: &str) -> Option<&Tool> {
+        self.tools.iter().find(|tool| tool.id == id)
+    }
+
+    fn hand(&self) -> &Tool {
+        self.get(HAND_ID).expect("the hand is added when tools.ron is loaded")
+    }
+}
+
+pub struct OwnedTool {
+    pub id: String,
+    // Hits left, None never breaks
+    pub durability: Option<u32>,
+}
+
+impl OwnedTool {
+    fn new(tool: &Tool) -> Self {
+        Self { id: tool.id.clone(), durability: tool.durability }
+    }
+}
+
+// Tools carried by an entity. The hand is always the first one.
+#[derive(Component)]
+pub struct Toolbelt {
+    pub tools: Vec<OwnedTool>,
+    pub equipped: usize,
+}
+
+impl Toolbelt {
+    pub fn new(tools: &Tools) -> Self {
+        let mut toolbelt = Self { tools: vec![OwnedTool::new(tools.hand())], equipped: 0 };
+        for tool in tools.starting_tools.iter().filter_map(|id| tools.get(id)) {
+            toolbelt.add(tool);
+        }
+        toolbelt
+    }
+
+    pub fn add(&mut self, tool: &Tool) {
+        self.tools.push(OwnedTool::new(tool));
+    }
+
+    // Data of the equipped tool, the hand if it is unknown
+    pub fn tool<'a>(&self, tools: &'a Tools) -> &'a Tool {
+        self.tools
+            .get(self.equipped)
+            .and_then(|owned| tools.get(&owned.id))
+            .unwrap_or_else(|| tools.hand())
+    }
+
+    // Uses up one hit of the equipped tool, returns it when it broke
+    pub fn wear(&mut self) -> Option<OwnedTool> {
+        let owned = self.tools.get_mut(self.equipped)?;
+        let durability = owned.durability.as_mut()?;
+        *durability = durability.saturating_sub(1);
+        if *durability > 0 || self.equipped == 0 {
+            return None;
+        }
+        let broken = self.tools.remove(self.equipped);
+        self.equipped = 0;
+        Some(broken)
+    }
+}
+
+// Seconds until the next strike. Kept out of Toolbelt so counting it down
+// does not mark the toolbelt as changed every frame.
+#[derive(Component, Default)]
+pub struct ToolCooldown(f32);
+
+impl ToolCooldown {
+    // Starts the cooldown of the tool if it is ready to strike
+    pub fn try_strike(&mut self, tool: &Tool) -> bool {
+        if self.0 > 0.0 {
+            return false;
+        }
+        self.0 = tool.cooldown;
+        true
+    }
+}
+
+// Shows the equipped tool over the player sprite
+#[derive(Component)]
+struct ToolSprite;
+
+#[derive(Component)]
+struct ToolHudText;
+
+#[derive(Resource)]
+struct ToolHandles {
+    mesh: Handle<Mesh>,
+    materials: HashMap<String, Handle<CustomMaterial>>,
+}
+
+fn setup_tools(
+    mut commands: Commands,
+    tools: Res<Tools>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut custom_materials: ResMut<Assets<CustomMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    let materials = tools
+        .tools
+        .iter()
+        .filter_map(|tool| {
+            let image = tool.image.as_ref()?;
+            let mut material = CustomMaterial::new(Color::WHITE, &asset_server.load(image.as_str()))
+                .with_alpha_mode(AlphaMode::Mask(SPRITE_ALPHA_CUTOFF));
+            // Drawn on a child of the billboarded player, so it has to bend along with it
+            material.bend_from_origin = true;
+            Some((tool.id.clone(), custom_materials.add(material)))
+        })
+        .collect();
+    commands.insert_resource(ToolHandles { mesh: meshes.add(Mesh::from(shape::Plane { size: 1.0 })), materials });
+
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("monogram.ttf"),
+                font_size: 30.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_style(Style {
+            margin: UiRect::all(Val::Px(5.0)),
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(10.0),
+                bottom: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        }),
+        ToolHudText,
+    ));
+}
+
+fn spawn_tool_sprite_system(
+    mut commands: Commands,
+    query: Query<Entity, Added<Toolbelt>>,
+    handles: Res<ToolHandles>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn((
+                MaterialMeshBundle::<CustomMaterial> {
+                    mesh: handles.mesh.clone(),
+                    // Slightly in front of the sprite, low and to the side. The player quad is twice as tall as wide.
+                    transform: Transform::from_xyz(0.3, 0.02, -0.15).with_scale(Vec3::new(0.4, 1.0, 0.2)),
+                    visibility: Visibility { is_visible: false },
+                    ..default()
+                },
+                NotShadowCaster,
+                ToolSprite,
+            ));
+        });
+    }
+}
+
+fn tool_cooldown_system(mut query: Query<&mut ToolCooldown>, time: Res<Time>) {
+    for mut cooldown in query.iter_mut() {
+        if cooldown.0 > 0.0 {
+            cooldown.0 -= time.delta_seconds();
+        }
+    }
+}
+
+fn toolbelt_system(mut query: Query<&mut Toolbelt>, actions: Res<ActionState>, build_mode: Res<BuildMode>) {
+    for mut toolbelt in query.iter_mut() {
+        // In build mode NextTool picks the block type instead
+        if actions.just_pressed(Action::NextTool) && !build_mode.active && !toolbelt.tools.is_empty() {
+            toolbelt.equipped = (toolbelt.equipped + 1) % toolbelt.tools.len();
+        }
+    }
+}
+
+fn tool_display_system(
+    toolbelt_query: Query<(&Toolbelt, &Children), Or<(Changed<Toolbelt>, Changed<Children>)>>,
+    mut sprite_query: Query<(&mut Handle<CustomMaterial>, &mut Visibility), With<ToolSprite>>,
+    mut text_query: Query<&mut Text, With<ToolHudText>>,
+    tools: Res<Tools>,
+    handles: Res<ToolHandles>,
+) {
+    for (toolbelt, children) in toolbelt_query.iter() {
+        let tool = toolbelt.tool(&tools);
+        let material = handles.materials.get(&tool.id);
+        for child in children.iter() {
+            if let Ok((mut handle, mut visibility)) = sprite_query.get_mut(*child) {
+                visibility.is_visible = material.is_some();
+                if let Some(material) = material {
+                    if *handle != *material {
+                        *handle = material.clone();
+                    }
+                }
+            }
+        }
+
+        let durability = toolbelt.tools.get(toolbelt.equipped).and_then(|owned| owned.durability);
+        let hud = match (durability, tool.durability) {
+            (Some(left), Some(max)) => format!("{} ({left}/{max})", tool.name),
+            _ => tool.name.clone(),
+        };
+        for mut text in text_query.iter_mut() {
+            if let Some(section) = text.sections.first_mut() {
+                section.value = hud.clone();
+            }
+        }
+    }
+}
+
+pub struct ToolsPlugin;
+
+impl Plugin for ToolsPlugin {
+    fn build(&self, app: &mut App) {
+        let mut tools = load_config::<Tools>("tools.ron");
+        if tools.get(HAND_ID).is_none() {
+            warn!("tools.ron has no \"{HAND_ID}\" tool, using the default hand");
+            tools.tools.push(Tool::default());
+        }
+
+        app.insert_resource(tools)
+            .add_startup_system(setup_tools)
+            .add_system(spawn_tool_sprite_system)
+            .add_system(tool_cooldown_system)
+            .add_system(toolbelt_system)
+            .add_system(tool_display_system);
+    }
+}
@@ -0,0 +1,356 @@
+use bevy::{pbr::NotShadowCaster, prelude::*, render::texture::DEFAULT_IMAGE_HANDLE, utils::HashMap};
+use serde::Deserialize;
+
+use crate::{
+    config::load_config,
+    culling::BendCulling,
+    shaders::CustomMaterial,
+    utils::{generate_random_between, generate_random_number},
+    Player, RootResource,
+};
+
+// Particles spawn in a box of this half width above the player
+const WEATHER_RADIUS: f32 = 15.0;
+const WEATHER_HEIGHT: f32 = 12.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug, Deserialize)]
+pub enum WeatherKind {
+    #[default]
+    Clear,
+    Rain,
+    Mist,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+struct WeatherState {
+    // Chance of picking this state relative to the others
+    weight: f32,
+    min_duration: f32,
+    max_duration: f32,
+    // Multiplies the fog distance
+    fog_distance: f32,
+    // Mixed into the fog colour by fog_tint_amount
+    fog_tint: Color,
+    fog_tint_amount: f32,
+    // Multiplies the sun brightness
+    sunlight: f32,
+    // Multiplies the sap gained from roots
+    sap_yield: f32,
+    // Multiplies the wind strength
+    wind: f32,
+    sound: Option<String>,
+    volume: f32,
+    particles: Option<WeatherParticles>,
+}
+
+impl Default for WeatherState {
+    fn default() -> Self {
+        Self {
+            weight: 1.0,
+            min_duration: 60.0,
+            max_duration: 120.0,
+            fog_distance: 1.0,
+            fog_tint: Color::WHITE,
+            fog_tint_amount: 0.0,
+            sunlight: 1.0,
+            sap_yield: 1.0,
+            wind: 1.0,
+            sound: None,
+            volume: 0.5,
+            particles: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+struct WeatherParticles {
+    // Particles spawned per second around the player
+    rate: f32,
+    velocity: Vec3,
+    // Random extra velocity in every direction
+    jitter: f32,
+    size: Vec3,
+    color: Color,
+    lifetime: f32,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct WeatherConfig {
+    states: HashMap<WeatherKind, WeatherState>,
+    initial: WeatherKind,
+    // Seconds it takes to fade from one state to the next
+    transition_time: f32,
+}
+
+#[derive(Resource)]
+pub struct Weather {
+    states: HashMap<WeatherKind, WeatherState>,
+    transition_time: f32,
+    pub current: WeatherKind,
+    previous: WeatherKind,
+    // 0.0 right after a change, 1.0 when the previous state has faded out
+    blend: f32,
+    time_left: f32,
+    sound: Option<Handle<AudioSink>>,
+    // Set when the state changes so the sound of the new state is started
+    restart_sound: bool,
+    // Fraction of a particle left over from the previous frame
+    particle_budget: f32,
+}
+
+impl Weather {
+    fn state(&self, kind: WeatherKind) -> WeatherState {
+        self.states.get(&kind).cloned().unwrap_or_default()
+    }
+
+    fn blended(&self, value: impl Fn(&WeatherState) -> f32) -> f32 {
+        let previous = value(&self.state(self.previous));
+        previous + (value(&self.state(self.current)) - previous) * self.blend
+    }
+
+    pub fn fog_distance(&self) -> f32 {
+        self.blended(|s| s.fog_distance)
+    }
+
+    pub fn sunlight(&self) -> f32 {
+        self.blended(|s| s.sunlight)
+    }
+
+    pub fn sap_yield(&self) -> f32 {
+        self.blended(|s| s.sap_yield)
+    }
+
+    // Multiplies what mining a block of the resource gives
+    pub fn yield_of(&self, resource: RootResource) -> f32 {
+        match resource {
+            RootResource::Sap => self.sap_yield(),
+            _ => 1.0,
+        }
+    }
+
+    pub fn wind(&self) -> f32 {
+        self.blended(|s| s.wind)
+    }
+
+    pub fn previous(&self) -> WeatherKind {
+        self.previous
+    }
+
+    // 0.0 right after a change, 1.0 when the previous state has faded out
+    pub fn blend(&self) -> f32 {
+        self.blend
+    }
+
+    pub fn tint_fog(&self, fog_color: Color) -> Color {
+        let mix = |state: &WeatherState, channel: fn(&Color) -> f32| {
+            channel(&fog_color) + (channel(&state.fog_tint) - channel(&fog_color)) * state.fog_tint_amount
+        };
+        Color::rgb(
+            self.blended(|s| mix(s, Color::r)),
+            self.blended(|s| mix(s, Color::g)),
+            self.blended(|s| mix(s, Color::b)),
+        )
+    }
+
+    // Switches to a new state, fading over the transition time
+    pub fn set(&mut self, kind: WeatherKind) {
+        if kind == self.current {
+            return;
+        }
+        self.previous = self.current;
+        self.current = kind;
+        self.blend = 0.0;
+        self.time_left = self.random_duration(kind);
+        self.restart_sound = true;
+    }
+
+    fn random_duration(&self, kind: WeatherKind) -> f32 {
+        let state = self.state(kind);
+        let mut rng = rand::thread_rng();
+        generate_random_between(&mut rng, state.min_duration, state.max_duration.max(state.min_duration))
+    }
+
+    fn pick_next(&self) -> WeatherKind {
+        let mut rng = rand::thread_rng();
+        let total: f32 = self.states.values().map(|s| s.weight.max(0.0)).sum();
+        let mut roll = generate_random_number(&mut rng) * total;
+        for (kind, state) in self.states.iter() {
+            roll -= state.weight.max(0.0);
+            if roll <= 0.0 {
+                return *kind;
+            }
+        }
+        self.current
+    }
+}
+
+#[derive(Resource)]
+struct WeatherHandles {
+    mesh: Handle<Mesh>,
+    materials: HashMap<WeatherKind, Handle<CustomMaterial>>,
+}
+
+#[derive(Component)]
+struct WeatherParticle {
+    velocity: Vec3,
+    lifetime_left: f32,
+}
+
+fn setup_weather(
+    mut commands: Commands,
+    weather: Res<Weather>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut custom_materials: ResMut<Assets<CustomMaterial>>,
+) {
+    let texture = DEFAULT_IMAGE_HANDLE.typed();
+    let materials = weather
+        .states
+        .iter()
+        .filter_map(|(kind, state)| {
+            let particles = state.particles.as_ref()?;
+            Some((*kind, custom_materials.add(CustomMaterial::new(particles.color, &texture))))
+        })
+        .collect();
+
+    commands.insert_resource(WeatherHandles {
+        mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+        materials,
+    });
+}
+
+fn weather_system(
+    mut weather: ResMut<Weather>,
+    audio: Res<Audio>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    asset_server: Res<AssetServer>,
+    time: Res<Time>,
+) {
+    weather.time_left -= time.delta_seconds();
+    if weather.time_left <= 0.0 {
+        let next = weather.pick_next();
+        if next == weather.current {
+            weather.time_left = weather.random_duration(next);
+        } else {
+            weather.set(next);
+        }
+    }
+
+    if weather.restart_sound {
+        weather.restart_sound = false;
+        if let Some(sink) = weather.sound.take().and_then(|handle| audio_sinks.get(&handle)) {
+            sink.stop();
+        }
+        if let Some(sound) = weather.state(weather.current).sound {
+            let sink = audio.play_with_settings(
+                asset_server.load(sound.as_str()),
+                PlaybackSettings { repeat: true, volume: 0.0, ..default() },
+            );
+            weather.sound = Some(audio_sinks.get_handle(sink));
+        }
+    }
+
+    if weather.blend < 1.0 {
+        weather.blend = if weather.transition_time > 0.0 {
+            (weather.blend + time.delta_seconds() / weather.transition_time).min(1.0)
+        } else {
+            1.0
+        };
+    }
+
+    // Fade the sound in with the new state
+    if let Some(sink) = weather.sound.as_ref().and_then(|handle| audio_sinks.get(handle)) {
+        sink.set_volume(weather.state(weather.current).volume * weather.blend);
+    }
+}
+
+fn weather_particle_system(
+    mut commands: Commands,
+    mut weather: ResMut<Weather>,
+    handles: Res<WeatherHandles>,
+    player_query: Query<&Transform, With<Player>>,
+    mut particle_query: Query<(Entity, &mut WeatherParticle, &mut Transform), Without<Player>>,
+    time: Res<Time>,
+) {
+    let mut rng = rand::thread_rng();
+
+    for (entity, mut particle, mut transform) in particle_query.iter_mut() {
+        transform.translation += particle.velocity * time.delta_seconds();
+        particle.lifetime_left -= time.delta_seconds();
+        if particle.lifetime_left <= 0.0 || transform.translation.y < -1.0 {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    let Ok(player_t) = player_query.get_single() else {
+        return;
+    };
+
+    // Both states spawn their own particles while fading
+    let spawns = [(weather.previous, 1.0 - weather.blend), (weather.current, weather.blend)];
+    let mut budget = weather.particle_budget;
+    for (kind, amount) in spawns {
+        let state = weather.state(kind);
+        let (Some(particles), Some(material)) = (state.particles, handles.materials.get(&kind)) else {
+            continue;
+        };
+
+        budget += particles.rate * amount * time.delta_seconds();
+        while budget >= 1.0 {
+            budget -= 1.0;
+            let offset = Vec3::new(
+                generate_random_between(&mut rng, -WEATHER_RADIUS, WEATHER_RADIUS),
+                generate_random_between(&mut rng, 0.0, WEATHER_HEIGHT),
+                generate_random_between(&mut rng, -WEATHER_RADIUS, WEATHER_RADIUS),
+            );
+            let jitter = Vec3::new(
+                generate_random_between(&mut rng, -1.0, 1.0),
+                generate_random_between(&mut rng, -1.0, 1.0),
+                generate_random_between(&mut rng, -1.0, 1.0),
+            ) * particles.jitter;
+
+            commands.spawn((
+                MaterialMeshBundle {
+                    mesh: handles.mesh.clone(),
+                    material: material.clone(),
+                    transform: Transform::from_translation(player_t.translation + offset)
+                        .with_scale(particles.size),
+                    ..default()
+                },
+                WeatherParticle {
+                    velocity: particles.velocity + jitter,
+                    lifetime_left: particles.lifetime,
+                },
+                NotShadowCaster,
+                BendCulling::default(),
+            ));
+        }
+    }
+    weather.particle_budget = budget;
+}
+
+pub struct WeatherPlugin;
+
+impl Plugin for WeatherPlugin {
+    fn build(&self, app: &mut App) {
+        let config: WeatherConfig = load_config("weather.ron");
+        let mut weather = Weather {
+            states: config.states,
+            transition_time: config.transition_time,
+            current: config.initial,
+            previous: config.initial,
+            blend: 1.0,
+            time_left: 0.0,
+            sound: None,
+            restart_sound: true,
+            particle_budget: 0.0,
+        };
+        weather.time_left = weather.random_duration(config.initial);
+
+        app.insert_resource(weather)
+            .add_startup_system(setup_weather)
+            .add_system(weather_system)
+            .add_system(weather_particle_system);
+    }
+}
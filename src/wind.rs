@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Velocity;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::load_config, constants::WALK_ANIMATION_SPEED, shaders::ShaderGlobals, weather::Weather,
+    Player,
+};
+
+// Seconds for foliage to bend away once the player starts walking through it
+const PUSH_BEND_TIME: f32 = 0.15;
+
+// Wind that sways foliage sprites with a non-zero CustomMaterial::sway
+#[derive(Resource, Reflect, Serialize, Deserialize, Clone)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct Wind {
+    // Horizontal direction, only x and z are used
+    pub direction: Vec3,
+    pub strength: f32,
+    // Speed of the gusts in radians per second
+    pub gust_frequency: f32,
+    // Foliage closer than this to the walking player is pushed aside
+    pub push_radius: f32,
+    pub push_strength: f32,
+    // Seconds for pushed foliage to straighten up again after the player stops
+    pub push_recovery: f32,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::new(1.0, 0.0, 0.3),
+            strength: 0.2,
+            gust_frequency: 1.5,
+            push_radius: 1.0,
+            push_strength: 0.6,
+            push_recovery: 0.8,
+        }
+    }
+}
+
+fn wind_system(
+    wind: Res<Wind>,
+    weather: Res<Weather>,
+    player_query: Query<&Velocity, With<Player>>,
+    mut globals: ResMut<ShaderGlobals>,
+    // How far foliage near the player is pushed aside, from 0 to 1
+    mut push: Local<f32>,
+    time: Res<Time>,
+) {
+    let direction = Vec2::new(wind.direction.x, wind.direction.z).normalize_or_zero();
+    globals.wind = direction * wind.strength * weather.wind();
+    globals.wind_frequency = wind.gust_frequency;
+    globals.wind_push_radius = wind.push_radius;
+
+    let walking = player_query
+        .get_single()
+        .map_or(false, |velocity| velocity.linvel.x.hypot(velocity.linvel.z) > WALK_ANIMATION_SPEED);
+    *push = if walking {
+        (*push + time.delta_seconds() / PUSH_BEND_TIME).min(1.0)
+    } else {
+        (*push - time.delta_seconds() / wind.push_recovery.max(0.001)).max(0.0)
+    };
+    globals.wind_push_strength = wind.push_strength * *push;
+}
+
+pub struct WindPlugin;
+
+impl Plugin for WindPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_config::<Wind>("wind.ron"))
+            .register_type::<Wind>() // Only needed for in-game inspector
+            .add_system(wind_system);
+    }
+}
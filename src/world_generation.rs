@@ -11,9 +11,7 @@ pub enum RootMode {
 }
 
 pub struct WorldGenerator<'a> {
-    pub cube_mesh: &'a Handle<Mesh>,
     pub plane_mesh: &'a Handle<Mesh>,
-    pub material_map: &'a HashMap<RootResource, Handle<CustomMaterial>>,
     pub ground_material: &'a Handle<CustomMaterial>,
     pub rng: &'a mut ThreadRng,
     pub blockmap: &'a mut BlockMap,
@@ -135,59 +133,27 @@ impl WorldGenerator<'_> {
         root_resource: RootResource,
         commands: &mut Commands,
     ) {
-        let material = self.material_map.get(&root_resource).unwrap(); // this will crash if material is not found
-        let block = self.spawn_block(position, material, commands);
-
-        let health = match root_resource {
-            RootResource::Sap => 1,
-            RootResource::Bark => 2,
-            RootResource::Wood => 4,
+        let root = Root {
+            id: i,
+            resource: root_resource,
+            mineable: generate_random_between(self.rng, 1, 5),
         };
-
-        commands
-            .entity(block)
-            .insert(Root {
-                id: i,
-                resource: root_resource,
-                mineable: generate_random_between(self.rng, 1, 5),
-            })
-            .insert(ActiveEvents::COLLISION_EVENTS)
-            .insert(Health { health })
-            .insert(Collider::cuboid(0.5, 0.5, 0.5));
-    }
-
-    pub fn spawn_block(
-        &mut self,
-        position: &Vec3i,
-        material: &Handle<CustomMaterial>,
-        commands: &mut Commands,
-    ) -> Entity {
-        let entity = commands
-            .spawn((
-                MaterialMeshBundle {
-                    mesh: self.cube_mesh.clone(),
-                    material: material.clone(),
-                    transform: (*position).into(),
-                    ..default()
-                },
-                BlockPosition(*position),
-                bevy::render::view::NoFrustumCulling,
-            ))
-            .id();
-        self.blockmap.entities.insert(*position, entity);
-        entity
+        spawn_root(position, root, self.blockmap, commands);
     }
 
     pub fn make_ground_plane(&mut self, commands: &mut Commands) {
         for x in LEVEL_MIN as i64..LEVEL_MAX as i64 {
             for z in LEVEL_MIN as i64..LEVEL_MAX as i64 {
                 // Ground block is simplified into a plane mesh
-                commands.spawn((MaterialMeshBundle {
-                    mesh: self.plane_mesh.clone(),
-                    material: self.ground_material.clone(),
-                    transform: Transform::from_translation(Vec3::new(x as f32, -0.5, z as f32)),
-                    ..default()
-                },));
+                commands.spawn((
+                    MaterialMeshBundle {
+                        mesh: self.plane_mesh.clone(),
+                        material: self.ground_material.clone(),
+                        transform: Transform::from_translation(Vec3::new(x as f32, -0.5, z as f32)),
+                        ..default()
+                    },
+                    NotShadowCaster,
+                ));
             }
         }
 
@@ -198,3 +164,25 @@ impl WorldGenerator<'_> {
         ));
     }
 }
+
+// Blocks are drawn by the chunk meshes, so the entity only carries the gameplay data.
+// Also used for blocks placed by the player.
+pub fn spawn_root(
+    position: &Vec3i,
+    root: Root,
+    blockmap: &mut BlockMap,
+    commands: &mut Commands,
+) -> Entity {
+    let entity = commands
+        .spawn((
+            TransformBundle::from_transform((*position).into()),
+            BlockPosition(*position),
+            Health::new(root.resource.health()),
+            root,
+            ActiveEvents::COLLISION_EVENTS,
+            Collider::cuboid(0.5, 0.5, 0.5),
+        ))
+        .id();
+    blockmap.insert(*position, entity);
+    entity
+}